@@ -0,0 +1,56 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use canter::{Config, Index, query::PhraseStrategy};
+
+fn main() {
+    let texts = (0..5_000)
+        .map(|document| {
+            (0..100)
+                .map(|word| format!("term{}", (document * 7 + word * 13) % 50))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>();
+
+    let phrases = [
+        "\"term0 term13\"",
+        "\"term0 term13 term26 term39\"",
+        "\"term0 term13 term26 term39 term2 term15 term28 term41\"",
+        "\"term0 term26\"~2",
+    ];
+
+    for phrase_strategy in [PhraseStrategy::Joins, PhraseStrategy::Intersection] {
+        let mut config = Config::default();
+        config.phrase_strategy = phrase_strategy;
+
+        let mut index = Index::in_memory(config).unwrap();
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            for (document_id, text) in texts.iter().enumerate() {
+                writer.add_text(document_id as i64, "field", text).unwrap();
+            }
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        for phrase in phrases {
+            let query = reader.parse(&format!("field:{phrase}")).unwrap();
+
+            let start = Instant::now();
+
+            let results = reader.search(black_box(&*query), None, None, None).unwrap();
+
+            println!(
+                "{phrase_strategy:?} {phrase}: {} results in {:?}",
+                results.len(),
+                start.elapsed()
+            );
+        }
+    }
+}
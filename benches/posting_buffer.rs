@@ -0,0 +1,37 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use canter::{Config, Index};
+
+fn main() {
+    let texts = (0..10_000)
+        .map(|document| {
+            (0..50)
+                .map(|word| format!("term{}", (document * 31 + word * 17) % 5_000))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>();
+
+    for posting_buffer in [None, Some(1_000), Some(100_000)] {
+        let mut config = Config::default();
+        config.posting_buffer = posting_buffer;
+
+        let mut index = Index::in_memory(config).unwrap();
+        index.add_field("field", "default").unwrap();
+
+        let start = Instant::now();
+
+        let mut writer = index.rewrite().unwrap();
+
+        for (document_id, text) in texts.iter().enumerate() {
+            writer
+                .add_text(black_box(document_id as i64), "field", text)
+                .unwrap();
+        }
+
+        writer.commit().unwrap();
+
+        println!("posting_buffer={posting_buffer:?}: {:?}", start.elapsed());
+    }
+}
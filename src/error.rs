@@ -12,10 +12,42 @@ pub enum Error {
         existing_tokenizer: String,
     },
     NoSuchField(String),
+    InvalidFieldName(String),
     NoSuchTokenizer(String),
+    DocumentConflict(i64),
+    TooManyTokens(i64),
+    TokenizerInUse {
+        name: String,
+        fields: Vec<String>,
+    },
+    FieldNotAllowed(String),
     MissingFieldName(String),
     UnclosedQuote(String),
+    UnbalancedParentheses(String),
+    DanglingOperator(String),
     InvalidValue(String),
+    InvalidRegex(String),
+    NoTokens {
+        field: String,
+        value: String,
+        dropped_by: String,
+    },
+    TooManyClauses(usize),
+    PositionsDisabled(String),
+    AnalysisMismatch {
+        field: String,
+        term: String,
+    },
+    ScoringConflict {
+        name: String,
+        value: Option<f64>,
+        stored: Option<f64>,
+    },
+    InvalidPayload {
+        token: String,
+        payload: f64,
+    },
+    Busy,
 }
 
 impl StdError for Error {}
@@ -33,10 +65,60 @@ impl fmt::Display for Error {
                 "Field `{name}` already defined, but using tokenizer `{existing_tokenizer}` instead of `{tokenizer}"
             ),
             Self::NoSuchField(name) => write!(fmt, "No such field: {name}"),
+            Self::InvalidFieldName(name) => write!(fmt, "Invalid field name: {name}"),
             Self::NoSuchTokenizer(name) => write!(fmt, "No such tokenizer: {name}"),
+            Self::DocumentConflict(id) => write!(fmt, "Document already present: {id}"),
+            Self::TooManyTokens(id) => write!(fmt, "Too many tokens in document: {id}"),
+            Self::TokenizerInUse { name, fields } => write!(
+                fmt,
+                "Tokenizer `{name}` still used by fields: {}",
+                fields.join(", ")
+            ),
+            Self::FieldNotAllowed(name) => write!(fmt, "Field not allowed: {name}"),
             Self::MissingFieldName(text) => write!(fmt, "Missing field name: {text}"),
             Self::UnclosedQuote(text) => write!(fmt, "Unclosed quote: {text}"),
+            Self::UnbalancedParentheses(text) => write!(fmt, "Unbalanced parentheses: {text}"),
+            Self::DanglingOperator(text) => write!(fmt, "Operator without operand: {text}"),
             Self::InvalidValue(text) => write!(fmt, "Invalid value: {text}"),
+            Self::InvalidRegex(text) => write!(fmt, "Invalid regular expression: {text}"),
+            Self::NoTokens {
+                field,
+                value,
+                dropped_by,
+            } => write!(
+                fmt,
+                "Value `{value}` produced no tokens for field `{field}` as they were dropped by `{dropped_by}`"
+            ),
+            Self::TooManyClauses(limit) => write!(fmt, "Too many clauses: more than {limit}"),
+            Self::PositionsDisabled(name) => {
+                write!(fmt, "Phrase query on field without positions: {name}")
+            }
+            Self::AnalysisMismatch { field, term } => write!(
+                fmt,
+                "Term `{term}` is indexed for field `{field}`, but not produced by analyzing its text"
+            ),
+            Self::ScoringConflict {
+                name,
+                value,
+                stored,
+            } => {
+                let describe = |value: &Option<f64>| match value {
+                    Some(value) => value.to_string(),
+                    None => "unset".to_owned(),
+                };
+
+                write!(
+                    fmt,
+                    "Scoring parameter `{name}` is {}, but {} in index",
+                    describe(value),
+                    describe(stored)
+                )
+            }
+            Self::InvalidPayload { token, payload } => write!(
+                fmt,
+                "Payload of token `{token}` must be finite and non-negative: {payload}"
+            ),
+            Self::Busy => write!(fmt, "All connections are in use"),
         }
     }
 }
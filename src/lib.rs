@@ -4,15 +4,21 @@ pub mod reader;
 pub mod tokenizer;
 pub mod writer;
 
-use std::ops::{Deref, DerefMut};
+use std::sync::{
+    Mutex, MutexGuard, PoisonError, TryLockError,
+    atomic::{AtomicU64, Ordering},
+};
 
 use hashbrown::hash_map::{EntryRef, HashMap};
+use regex::Regex;
 use rusqlite::{Connection, OptionalExtension, functions::FunctionFlags, params};
 
 use crate::{
     error::Error,
+    query::{Coordination, Decay, PhraseStrategy, edit_distance, match_phrase},
     tokenizer::{
-        ErasedTokenizer, LimitLength, SplitNonAlphanumeric, StubTokenizer, ToLowerCase, Tokenizer,
+        ErasedTokenizer, LimitLength, SplitKeepingDottedNumbers, SplitNonAlphanumeric,
+        StubTokenizer, ToLowerCase, Tokenizer, fold_accents,
     },
 };
 
@@ -21,13 +27,109 @@ use crate::{
 pub struct Config {
     pub bm25_k1: f64,
     pub bm25_b: f64,
-    pub fields: HashMap<String, FieldConfig>,
+    /// The constant `n` of the inverse document frequency
+    /// `ln((documents - df + n) / (df + d) + o)` used by BM25.
+    pub bm25_idf_numerator_smoothing: f64,
+    /// The constant `d` of the inverse document frequency, cf. [`Config::bm25_idf_numerator_smoothing`].
+    pub bm25_idf_denominator_smoothing: f64,
+    /// The constant `o` of the inverse document frequency, which keeps it positive
+    /// for terms occurring in most documents if at least one.
+    pub bm25_idf_offset: f64,
+    /// Caps the frequency of a term within a document used for scoring,
+    /// limiting the impact of documents stuffed with repeated keywords.
+    pub max_term_frequency: Option<usize>,
+    /// Multiplies the boost of every clause in parsed queries, including per-field boosts.
+    pub default_boost: f64,
+    /// Whether to skip recording field instances which produce no tokens,
+    /// so that they do not drag down the average field length.
+    pub skip_empty_fields: bool,
+    /// Limits the number of clauses in parsed queries, including those
+    /// produced by expanding the wildcard field `*` to all fields.
+    pub max_clauses: usize,
+    /// Bounds the number of rows examined per index by the `ANALYZE` run on commit
+    /// via `PRAGMA analysis_limit`, leaving the connection's setting unchanged if `None`.
+    pub analysis_limit: Option<usize>,
+    /// Limits the number of worker threads used by [`Writer::add_many`][writer::Writer::add_many].
+    pub max_parallelism: Option<usize>,
+    /// Buffers up to this many postings in the writer and inserts them ordered by term
+    /// when flushed, which happens on [`Writer::flush`][writer::Writer::flush], on commit
+    /// and before removing documents or fields, instead of inserting each one immediately.
+    ///
+    /// Buffered postings are not visible via the writer's connection and
+    /// errors inserting them are reported by the call which flushes them.
+    pub posting_buffer: Option<usize>,
+    /// How phrase queries match the positions of their terms.
+    pub phrase_strategy: PhraseStrategy,
+    /// The coordination used by parsed queries combining multiple clauses.
+    pub coordination: Coordination,
+    /// The schema containing the tables of the index, i.e. `main`, `temp`
+    /// or the name of a database attached to the connection before opening the index.
+    pub schema: String,
+    /// Whether [`Index::rewrite`] keeps the IDs of terms which are added again, e.g. so that
+    /// they can be cached externally, instead of removing all terms and assigning new IDs.
+    ///
+    /// The IDs of terms which are not added again are dropped and can be reused.
+    pub preserve_term_ids: bool,
+    /// Whether the `default` tokenizer splits text using [`SplitKeepingDottedNumbers`]
+    /// instead of [`SplitNonAlphanumeric`], e.g. so that `v2.0.1` is a single token.
+    ///
+    /// Changing this requires rebuilding the index.
+    pub keep_dotted_numbers: bool,
+    /// How the scoring parameters, i.e. `bm25_*`, `max_term_frequency` and the per-field
+    /// `bm25_k1` and `bm25_b`, are reconciled with those stored when the index was first opened.
+    pub stored_scoring: StoredScoring,
+    /// Options of individual fields, where fields without an entry use [`FieldConfig::default`].
+    pub fields: FieldConfigs,
 }
 
+/// Maps field names to their options.
+pub type FieldConfigs = HashMap<String, FieldConfig>;
+
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq)]
 pub struct FieldConfig {
+    /// Multiplies the scores of clauses in parsed queries which match this field,
+    /// defaulting to `1.0`.
     pub boost: f64,
+    /// Weight `w` of the factor `1 + w / p` applied to scores where `p` is
+    /// the earliest position of a match within the field, disabled if zero
+    /// or if [`positions`][Self::positions] are not recorded.
+    pub proximity_boost: f64,
+    /// Whether single terms in parsed queries also match indexed terms which differ
+    /// only by accents, e.g. `cafe` matching `café`, even if the field's tokenizer
+    /// does not fold them.
+    ///
+    /// Enabling this for any field maintains an additional index of the folded terms.
+    pub expand_accents: bool,
+    /// Whether to record the positions of terms, which phrase queries require.
+    /// Otherwise, a single posting per term and document is stored,
+    /// so that the term frequency is just its presence.
+    ///
+    /// Changing this requires rebuilding the index.
+    pub positions: bool,
+    /// Additional fields into which text added to this field is indexed,
+    /// each using its own tokenizer, e.g. a catch-all field combining several others.
+    ///
+    /// Copies are not followed further, i.e. the `copy_to` of the target fields is ignored.
+    pub copy_to: Vec<String>,
+    /// Whether single terms in parsed queries are scored using the sum of the payloads
+    /// of their postings within a document instead of their frequency, cf.
+    /// [`Writer::add_tokens_weighted`][writer::Writer::add_tokens_weighted].
+    pub sum_payloads: bool,
+    /// Whether text added via [`Writer::add_text`][writer::Writer::add_text] or
+    /// [`Writer::add_values`][writer::Writer::add_values] is also stored
+    /// as if added via [`Writer::add_stored_text`][writer::Writer::add_stored_text].
+    /// Otherwise, no space is taken up by the text itself.
+    ///
+    /// Pre-tokenized text added via [`Writer::add_tokens`][writer::Writer::add_tokens]
+    /// is never stored.
+    pub store: bool,
+    /// Overrides [`Config::bm25_k1`] for this field, i.e. how quickly
+    /// the score saturates with the term frequency.
+    pub bm25_k1: Option<f64>,
+    /// Overrides [`Config::bm25_b`] for this field, e.g. to reduce length normalization
+    /// for short titles.
+    pub bm25_b: Option<f64>,
 }
 
 impl Default for Config {
@@ -35,6 +137,22 @@ impl Default for Config {
         Self {
             bm25_k1: 2.0,
             bm25_b: 0.75,
+            bm25_idf_numerator_smoothing: 0.5,
+            bm25_idf_denominator_smoothing: 0.5,
+            bm25_idf_offset: 1.0,
+            max_term_frequency: None,
+            default_boost: 1.0,
+            skip_empty_fields: false,
+            max_clauses: 1024,
+            analysis_limit: None,
+            max_parallelism: None,
+            posting_buffer: None,
+            phrase_strategy: PhraseStrategy::Joins,
+            coordination: Coordination::Terms,
+            schema: "main".to_owned(),
+            preserve_term_ids: false,
+            keep_dotted_numbers: false,
+            stored_scoring: StoredScoring::Verify,
             fields: HashMap::new(),
         }
     }
@@ -42,66 +160,163 @@ impl Default for Config {
 
 impl Default for FieldConfig {
     fn default() -> Self {
-        Self { boost: 1.0 }
+        Self {
+            boost: 1.0,
+            proximity_boost: 0.0,
+            expand_accents: false,
+            positions: true,
+            copy_to: Vec::new(),
+            sum_payloads: false,
+            store: false,
+            bm25_k1: None,
+            bm25_b: None,
+        }
     }
 }
 
-pub struct Index {
-    conn: Connection,
-    config: Config,
-    tokenizers: Tokenizers,
-    fields: Fields,
+/// Determines how [`Index::open`] handles scoring parameters which differ
+/// from those stored when the index was first opened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoredScoring {
+    /// Fails with [`Error::ScoringConflict`].
+    Verify,
+    /// Uses the stored parameters instead, which [`Index::config`] then reflects.
+    Adopt,
+    /// Replaces the stored parameters.
+    Overwrite,
+}
+
+/// Computes the BM25 score of a term as done by the `canter_bm25` SQL function.
+///
+/// `documents` and `avg_len` describe the field, `df` is the number of occurrences of the term,
+/// and `tf` and `doc_len` are the number of its occurrences and the length of the field within the document.
+pub fn bm25(
+    config: &Config,
+    documents: usize,
+    avg_len: f64,
+    df: usize,
+    tf: usize,
+    doc_len: usize,
+) -> f64 {
+    bm25_weighted(config, documents, avg_len, df, tf as f64, doc_len)
 }
 
-impl Deref for Index {
-    type Target = Connection;
+/// Computes the BM25 score of a term like [`bm25`], but with a fractional term frequency,
+/// e.g. the sum of its payloads for fields with [`FieldConfig::sum_payloads`] set.
+pub fn bm25_weighted(
+    config: &Config,
+    documents: usize,
+    avg_len: f64,
+    df: usize,
+    tf: f64,
+    doc_len: usize,
+) -> f64 {
+    bm25_impl(
+        config,
+        (config.bm25_k1, config.bm25_b),
+        documents,
+        avg_len,
+        df,
+        tf,
+        doc_len,
+    )
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.conn
-    }
+fn bm25_impl(
+    config: &Config,
+    (k1, b): (f64, f64),
+    documents: usize,
+    avg_len: f64,
+    df: usize,
+    tf: f64,
+    doc_len: usize,
+) -> f64 {
+    let documents = documents as f64;
+    let df = df as f64;
+    let doc_len = doc_len as f64;
+
+    let tf = match config.max_term_frequency {
+        Some(max_term_frequency) => tf.min(max_term_frequency as f64),
+        None => tf,
+    };
+
+    let idf = bm25_idf(config, documents, df);
+
+    idf * (tf * (k1 + 1.0)) / (tf + k1 * (1.0 - b + b * doc_len / avg_len))
 }
 
-impl DerefMut for Index {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.conn
+impl Config {
+    /// Sets the [`boost`][FieldConfig::boost] of a field, keeping its other options.
+    pub fn set_field_boost(&mut self, field_name: &str, boost: f64) {
+        self.fields.entry_ref(field_name).or_default().boost = boost;
     }
+
+    /// The `k1` and `b` overridden for the given field, if any.
+    pub(crate) fn field_bm25(&self, field_name: &str) -> Option<(f64, f64)> {
+        let config = self.fields.get(field_name)?;
+
+        if config.bm25_k1.is_none() && config.bm25_b.is_none() {
+            return None;
+        }
+
+        Some((
+            config.bm25_k1.unwrap_or(self.bm25_k1),
+            config.bm25_b.unwrap_or(self.bm25_b),
+        ))
+    }
+}
+
+/// Computes the inverse document frequency factor of [`bm25`].
+pub(crate) fn bm25_idf(config: &Config, documents: f64, df: f64) -> f64 {
+    ((documents - df + config.bm25_idf_numerator_smoothing)
+        / (df + config.bm25_idf_denominator_smoothing)
+        + config.bm25_idf_offset)
+        .ln()
+}
+
+/// An index can be shared between threads. Readers hold a lock on a connection while they exist,
+/// so they are serialized unless additional connections are added via
+/// [`add_read_connection`][Self::add_read_connection].
+pub struct Index {
+    conn: Mutex<Connection>,
+    config: Config,
+    /// The quoted name of [`Config::schema`] qualifying all table names.
+    schema: String,
+    tokenizers: Mutex<Tokenizers>,
+    fields: Mutex<Fields>,
+    /// Identifies the state of the database for which `fields` was cached,
+    /// so that changes made bypassing the writer are detected.
+    data_version: Mutex<DataVersion>,
+    /// Counts how often the connection was handed out, as changes made using it
+    /// are not reflected by `PRAGMA data_version`.
+    generation: AtomicU64,
+    read_connections: Vec<ReadConnection>,
+}
+
+/// An additional connection used only by readers, with its own cache of field statistics
+/// as its transactions can observe a different state of the database.
+struct ReadConnection {
+    conn: Mutex<Connection>,
+    fields: Mutex<Fields>,
+    data_version: Mutex<DataVersion>,
 }
 
 impl Index {
-    pub fn open(mut conn: Connection, config: Config) -> Result<Self, Error> {
-        let bm25_k1 = config.bm25_k1;
-        let bm25_b = config.bm25_b;
-
-        conn.create_scalar_function(
-            "canter_bm25",
-            5,
-            FunctionFlags::SQLITE_DETERMINISTIC,
-            move |ctx| {
-                let documents = ctx.get::<usize>(0)? as f64;
-                let avg_documents_count = ctx.get::<f64>(1)?;
-                let terms_count = ctx.get::<usize>(2)? as f64;
-                let postings_count = ctx.get::<usize>(3)? as f64;
-                let documents_count = ctx.get::<usize>(4)? as f64;
-
-                let idf = ((documents - terms_count + 0.5) / (terms_count + 0.5) + 1.0).ln();
-
-                Ok(idf * (postings_count * (bm25_k1 + 1.0))
-                    / (postings_count
-                        + bm25_k1
-                            * (1.0 - bm25_b + bm25_b * documents_count / avg_documents_count)))
-            },
-        )?;
+    pub fn open(mut conn: Connection, mut config: Config) -> Result<Self, Error> {
+        create_functions(&conn)?;
 
         let txn = conn.transaction()?;
 
-        txn.execute_batch(
-            r#"CREATE TABLE IF NOT EXISTS canter_fields (
+        let schema = quote_identifier(&config.schema);
+
+        txn.execute_batch(&format!(
+            r#"CREATE TABLE IF NOT EXISTS {schema}.canter_fields (
                    id INTEGER PRIMARY KEY,
                    name TEXT NOT NULL UNIQUE,
                    tokenizer TEXT NOT NULL
                );
 
-               CREATE TABLE IF NOT EXISTS canter_terms (
+               CREATE TABLE IF NOT EXISTS {schema}.canter_terms (
                    id INTEGER PRIMARY KEY,
                    field_id INTEGER NOT NULL,
                    value TEXT NOT NULL,
@@ -109,53 +324,230 @@ impl Index {
                    UNIQUE (field_id, value)
                );
 
-               CREATE TABLE IF NOT EXISTS canter_postings (
+               CREATE TABLE IF NOT EXISTS {schema}.canter_postings (
                    term_id INTEGER NOT NULL,
                    document_id INTEGER NOT NULL,
                    position INTEGER NOT NULL,
+                   segment INTEGER NOT NULL DEFAULT 0,
+                   payload REAL NOT NULL DEFAULT 1.0,
                    PRIMARY KEY (term_id, document_id, position)
                )
                WITHOUT ROWID;
 
-               CREATE TABLE IF NOT EXISTS canter_documents (
+               CREATE TABLE IF NOT EXISTS {schema}.canter_documents (
                    field_id INTEGER NOT NULL,
                    document_id INTEGER NOT NULL,
                    count INTEGER NOT NULL,
                    PRIMARY KEY (field_id, document_id)
                )
-               WITHOUT ROWID;"#,
+               WITHOUT ROWID;
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_field_stats (
+                   field_id INTEGER PRIMARY KEY,
+                   documents INTEGER NOT NULL,
+                   total INTEGER NOT NULL
+               );
+
+               INSERT OR IGNORE INTO {schema}.canter_field_stats (field_id, documents, total)
+               SELECT field_id, COUNT(*), SUM(count) FROM {schema}.canter_documents GROUP BY field_id;
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_timestamps (
+                   document_id INTEGER PRIMARY KEY,
+                   timestamp INTEGER NOT NULL
+               );
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_sort (
+                   document_id INTEGER PRIMARY KEY,
+                   key INTEGER NOT NULL
+               );
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_stored (
+                   id INTEGER PRIMARY KEY,
+                   field_id INTEGER NOT NULL,
+                   document_id INTEGER NOT NULL,
+                   text TEXT NOT NULL,
+                   segmented INTEGER NOT NULL DEFAULT 0
+               );
+
+               CREATE INDEX IF NOT EXISTS {schema}.canter_stored_document
+               ON canter_stored (document_id, field_id);
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_docmap (
+                   id INTEGER PRIMARY KEY,
+                   external_id TEXT NOT NULL UNIQUE
+               );
+
+               CREATE TABLE IF NOT EXISTS {schema}.canter_meta (
+                   key TEXT PRIMARY KEY,
+                   value NOT NULL
+               );"#
+        ))?;
+
+        // The folded values are only looked up for fields expanding accents,
+        // so other indexes neither pay for maintaining them nor depend on `canter_fold`.
+        if config.fields.values().any(|config| config.expand_accents) {
+            txn.execute_batch(&format!(
+                r#"CREATE INDEX IF NOT EXISTS {schema}.canter_terms_folded
+                   ON canter_terms (field_id, canter_fold(value));"#
+            ))?;
+        } else {
+            txn.execute_batch(&format!(
+                "DROP INDEX IF EXISTS {schema}.canter_terms_folded;"
+            ))?;
+        }
+
+        let has_segment = txn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('canter_postings', ?) WHERE name = 'segment'",
+            params![config.schema],
+            |row| row.get::<_, usize>(0),
+        )?;
+
+        if has_segment == 0 {
+            txn.execute(
+                &format!(
+                    "ALTER TABLE {schema}.canter_postings ADD COLUMN segment INTEGER NOT NULL DEFAULT 0"
+                ),
+                (),
+            )?;
+        }
+
+        let has_payload = txn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('canter_postings', ?) WHERE name = 'payload'",
+            params![config.schema],
+            |row| row.get::<_, usize>(0),
+        )?;
+
+        if has_payload == 0 {
+            txn.execute(
+                &format!(
+                    "ALTER TABLE {schema}.canter_postings ADD COLUMN payload REAL NOT NULL DEFAULT 1.0"
+                ),
+                (),
+            )?;
+        }
+
+        let has_segmented = txn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('canter_stored', ?) WHERE name = 'segmented'",
+            params![config.schema],
+            |row| row.get::<_, usize>(0),
         )?;
 
+        if has_segmented == 0 {
+            txn.execute(
+                &format!(
+                    "ALTER TABLE {schema}.canter_stored ADD COLUMN segmented INTEGER NOT NULL DEFAULT 0"
+                ),
+                (),
+            )?;
+        }
+
+        reconcile_scoring(&txn, &mut config)?;
+
         txn.commit()?;
 
+        create_bm25_function(&conn, &config)?;
+
         let tokenizers = [
             ("stub".to_owned(), StubTokenizer.into()),
             (
                 "default".to_owned(),
-                SplitNonAlphanumeric
-                    .chain(LimitLength::default())
-                    .chain(ToLowerCase::default())
-                    .into(),
+                if config.keep_dotted_numbers {
+                    SplitKeepingDottedNumbers
+                        .chain(LimitLength::default())
+                        .chain(ToLowerCase::default())
+                        .into()
+                } else {
+                    SplitNonAlphanumeric
+                        .chain(LimitLength::default())
+                        .chain(ToLowerCase::default())
+                        .into()
+                },
             ),
         ]
         .into_iter()
         .collect();
 
         Ok(Self {
-            conn,
+            conn: Mutex::new(conn),
             config,
-            tokenizers,
-            fields: HashMap::new(),
+            schema,
+            tokenizers: Mutex::new(tokenizers),
+            fields: Mutex::new(HashMap::new()),
+            data_version: Mutex::new(None),
+            generation: AtomicU64::new(0),
+            read_connections: Vec::new(),
         })
     }
 
+    /// Adds a connection to the same database which readers use if the others are busy,
+    /// so that multiple readers can exist at the same time.
+    ///
+    /// The connection must already have the database containing the index attached
+    /// if [`Config::schema`] names an attached database. Temporary tables are only visible
+    /// to readers using the connection which created them.
+    pub fn add_read_connection(&mut self, conn: Connection) -> Result<(), Error> {
+        create_functions(&conn)?;
+        create_bm25_function(&conn, &self.config)?;
+
+        self.read_connections.push(ReadConnection {
+            conn: Mutex::new(conn),
+            fields: Mutex::new(HashMap::new()),
+            data_version: Mutex::new(None),
+        });
+
+        Ok(())
+    }
+
+    /// The configuration in effect, including scoring parameters adopted from the index,
+    /// cf. [`StoredScoring::Adopt`].
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Locks the main connection, waiting for any reader using it.
+    ///
+    /// As with [`read`][Self::read], waiting never ends if that reader is held by the calling thread.
+    pub fn connection(&self) -> MutexGuard<'_, Connection> {
+        let conn = lock(&self.conn);
+
+        self.generation.fetch_add(1, Ordering::Relaxed);
+
+        conn
+    }
+
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        *self.generation.get_mut() += 1;
+
+        get_mut(&mut self.conn)
+    }
+
+    pub fn in_memory(config: Config) -> Result<Self, Error> {
+        let conn = Connection::open_in_memory()?;
+
+        Self::open(conn, config)
+    }
+
+    /// Defines a field analyzed by the given tokenizer.
+    ///
+    /// Field names may contain any characters except `:` and whitespace, must not start
+    /// with `+` or `-` and must not be the wildcard `*`, so that they can be referenced
+    /// by [`Reader::parse`][reader::Reader::parse], e.g. `meta.author:foo`.
     pub fn add_field(&mut self, name: &str, tokenizer: &str) -> Result<(), Error> {
-        let txn = self.conn.transaction()?;
+        if name.is_empty()
+            || name == "*"
+            || name.starts_with(['+', '-'])
+            || name.contains(|char_: char| char_ == ':' || char_.is_whitespace())
+        {
+            return Err(Error::InvalidFieldName(name.to_owned()));
+        }
+
+        let schema = &self.schema;
+        let txn = get_mut(&mut self.conn).transaction()?;
 
         {
             let existing_tokenizer = txn
                 .query_row(
-                    "SELECT tokenizer FROM canter_fields WHERE name = ?",
+                    &format!("SELECT tokenizer FROM {schema}.canter_fields WHERE name = ?"),
                     params![name],
                     |row| row.get::<_, String>(0),
                 )
@@ -175,8 +567,9 @@ impl Index {
         }
 
         {
-            let mut stmt =
-                txn.prepare("INSERT INTO canter_fields (name, tokenizer) VALUES (?, ?)")?;
+            let mut stmt = txn.prepare(&format!(
+                "INSERT INTO {schema}.canter_fields (name, tokenizer) VALUES (?, ?)"
+            ))?;
 
             stmt.execute(params![name, tokenizer])?;
         }
@@ -185,6 +578,343 @@ impl Index {
 
         Ok(())
     }
+
+    /// Counts the rows of the main tables of the index and the size of the database containing it.
+    pub fn stats(&self) -> Result<IndexSizeStats, Error> {
+        let conn = lock(&self.conn);
+
+        let schema = &self.schema;
+
+        let stats = conn.query_row(
+            &format!(
+                r#"SELECT
+                       (SELECT COUNT(*) FROM {schema}.canter_fields),
+                       (SELECT COUNT(*) FROM {schema}.canter_terms),
+                       (SELECT COUNT(*) FROM {schema}.canter_postings),
+                       (SELECT COUNT(DISTINCT document_id) FROM {schema}.canter_documents),
+                       (SELECT page_count FROM pragma_page_count(?1)) * (SELECT page_size FROM pragma_page_size(?1))"#
+            ),
+            params![self.config.schema],
+            |row| {
+                Ok(IndexSizeStats {
+                    fields: row.get(0)?,
+                    terms: row.get(1)?,
+                    postings: row.get(2)?,
+                    documents: row.get(3)?,
+                    bytes: row.get(4)?,
+                })
+            },
+        )?;
+
+        Ok(stats)
+    }
+}
+
+/// The size of an index as returned by [`Index::stats`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexSizeStats {
+    pub fields: usize,
+    pub terms: usize,
+    pub postings: usize,
+    /// The number of distinct documents with any field.
+    pub documents: usize,
+    /// The size of the whole database containing the index, including any other tables.
+    pub bytes: u64,
+}
+
+/// Registers the SQL functions used by queries which do not depend on the configuration.
+fn create_functions(conn: &Connection) -> Result<(), Error> {
+    conn.create_scalar_function(
+        "canter_regexp",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let regex = ctx.get_or_create_aux(0, |value| {
+                Regex::new(value.as_str()?)
+                    .map_err(|err| rusqlite::Error::UserFunctionError(Box::new(err)))
+            })?;
+
+            let value = ctx.get_raw(1).as_str()?;
+
+            Ok(regex.is_match(value))
+        },
+    )?;
+
+    conn.create_scalar_function(
+        "canter_edit_distance",
+        3,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let lhs = ctx.get_raw(0).as_str()?;
+            let rhs = ctx.get_raw(1).as_str()?;
+            let max = ctx.get::<usize>(2)?;
+
+            Ok(edit_distance(lhs, rhs, max))
+        },
+    )?;
+
+    conn.create_scalar_function(
+        "canter_fold",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let value = ctx.get_raw(0).as_str()?;
+
+            Ok(fold_accents(value))
+        },
+    )?;
+
+    conn.create_scalar_function(
+        "canter_phrase",
+        -1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let min_position = ctx.get::<bool>(0)?;
+            let slop = ctx.get::<i64>(1)?;
+            let first = parse_positions(ctx.get_raw(2).as_str()?);
+
+            let mut others = Vec::new();
+
+            for idx in (3..ctx.len()).step_by(2) {
+                let offset = ctx.get::<i64>(idx)?;
+                let positions = parse_positions(ctx.get_raw(idx + 1).as_str()?);
+
+                others.push((offset, positions));
+            }
+
+            Ok(match_phrase(slop, &first, &others).map(|(factor, start)| {
+                if min_position { start as f64 } else { factor }
+            }))
+        },
+    )?;
+
+    conn.create_scalar_function(
+        "canter_decay",
+        3,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let decay = ctx.get::<u8>(0)?;
+            let distance = ctx.get::<Option<f64>>(1)?;
+            let scale = ctx.get::<f64>(2)?;
+
+            let Some(distance) = distance else {
+                return Ok(1.0);
+            };
+
+            Ok(match Decay::from_sql(decay) {
+                Decay::Exponential => 0.5_f64.powf(distance.abs() / scale),
+                Decay::Gaussian => 0.5_f64.powf((distance / scale).powi(2)),
+            })
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Registers the `canter_bm25` SQL function using the scoring parameters of `config`.
+fn create_bm25_function(conn: &Connection, config: &Config) -> Result<(), Error> {
+    let bm25_config = config.clone();
+
+    // The optional sixth and seventh arguments override `k1` and `b` for a single field.
+    conn.create_scalar_function(
+        "canter_bm25",
+        -1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let params = if ctx.len() == 7 {
+                (ctx.get(5)?, ctx.get(6)?)
+            } else {
+                (bm25_config.bm25_k1, bm25_config.bm25_b)
+            };
+
+            Ok(bm25_impl(
+                &bm25_config,
+                params,
+                ctx.get(0)?,
+                ctx.get(1)?,
+                ctx.get(2)?,
+                ctx.get(3)?,
+                ctx.get(4)?,
+            ))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Stores the scoring parameters of `config` when an index is first opened
+/// and reconciles them with the stored ones afterwards according to [`Config::stored_scoring`].
+///
+/// Optional parameters, i.e. `max_term_frequency` and the per-field overrides,
+/// are only stored if set, so that a missing entry means they are unset.
+fn reconcile_scoring(conn: &Connection, config: &mut Config) -> Result<(), Error> {
+    let schema = quote_identifier(&config.schema);
+
+    let stored = {
+        let mut stmt = conn.prepare(&format!("SELECT key, value FROM {schema}.canter_meta"))?;
+
+        stmt.query_map((), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+        })?
+        .collect::<Result<HashMap<_, _>, _>>()?
+    };
+
+    let current = scoring_params(config);
+
+    if stored.is_empty() || config.stored_scoring == StoredScoring::Overwrite {
+        if stored != current.iter().cloned().collect() {
+            conn.execute(&format!("DELETE FROM {schema}.canter_meta"), ())?;
+
+            let mut stmt = conn.prepare(&format!(
+                "INSERT INTO {schema}.canter_meta (key, value) VALUES (?, ?)"
+            ))?;
+
+            for (name, value) in &current {
+                stmt.execute(params![name, value])?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Parameters which are only stored are checked last, in a stable order.
+    let mut unset = stored
+        .keys()
+        .filter(|name| current.iter().all(|(other, _)| other != *name))
+        .collect::<Vec<_>>();
+    unset.sort_unstable();
+
+    let names = current.iter().map(|(name, _)| name).chain(unset);
+
+    for name in names {
+        let value = current
+            .iter()
+            .find(|(other, _)| other == name)
+            .map(|(_, value)| *value);
+        let stored = stored.get(name).copied();
+
+        if value == stored {
+            continue;
+        }
+
+        match config.stored_scoring {
+            StoredScoring::Verify => {
+                return Err(Error::ScoringConflict {
+                    name: name.clone(),
+                    value,
+                    stored,
+                });
+            }
+            StoredScoring::Adopt => set_scoring_param(config, name, stored),
+            StoredScoring::Overwrite => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the scoring parameters of `config` which are set together with their names,
+/// using `fields.<field>.<parameter>` for per-field overrides ordered by field name.
+fn scoring_params(config: &Config) -> Vec<(String, f64)> {
+    let mut params = vec![
+        ("bm25_k1".to_owned(), config.bm25_k1),
+        ("bm25_b".to_owned(), config.bm25_b),
+        (
+            "bm25_idf_numerator_smoothing".to_owned(),
+            config.bm25_idf_numerator_smoothing,
+        ),
+        (
+            "bm25_idf_denominator_smoothing".to_owned(),
+            config.bm25_idf_denominator_smoothing,
+        ),
+        ("bm25_idf_offset".to_owned(), config.bm25_idf_offset),
+    ];
+
+    if let Some(max_term_frequency) = config.max_term_frequency {
+        params.push(("max_term_frequency".to_owned(), max_term_frequency as f64));
+    }
+
+    let mut fields = config.fields.iter().collect::<Vec<_>>();
+    fields.sort_unstable_by_key(|(field_name, _)| *field_name);
+
+    for (field_name, field_config) in fields {
+        if let Some(bm25_k1) = field_config.bm25_k1 {
+            params.push((format!("fields.{field_name}.bm25_k1"), bm25_k1));
+        }
+
+        if let Some(bm25_b) = field_config.bm25_b {
+            params.push((format!("fields.{field_name}.bm25_b"), bm25_b));
+        }
+    }
+
+    params
+}
+
+/// Sets the scoring parameter of `config` named as by [`scoring_params`],
+/// where `None` unsets optional parameters and leaves others unchanged.
+fn set_scoring_param(config: &mut Config, name: &str, value: Option<f64>) {
+    match name {
+        "bm25_k1" => config.bm25_k1 = value.unwrap_or(config.bm25_k1),
+        "bm25_b" => config.bm25_b = value.unwrap_or(config.bm25_b),
+        "bm25_idf_numerator_smoothing" => {
+            config.bm25_idf_numerator_smoothing =
+                value.unwrap_or(config.bm25_idf_numerator_smoothing)
+        }
+        "bm25_idf_denominator_smoothing" => {
+            config.bm25_idf_denominator_smoothing =
+                value.unwrap_or(config.bm25_idf_denominator_smoothing)
+        }
+        "bm25_idf_offset" => config.bm25_idf_offset = value.unwrap_or(config.bm25_idf_offset),
+        "max_term_frequency" => {
+            config.max_term_frequency = value.map(|value| value as usize);
+        }
+        name => {
+            let Some((field_name, param)) = name
+                .strip_prefix("fields.")
+                .and_then(|name| name.rsplit_once('.'))
+            else {
+                return;
+            };
+
+            let field_config = config.fields.entry_ref(field_name).or_default();
+
+            match param {
+                "bm25_k1" => field_config.bm25_k1 = value,
+                "bm25_b" => field_config.bm25_b = value,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Quotes an identifier, e.g. a schema name, so that it can contain arbitrary characters.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn parse_positions(positions: &str) -> Vec<i64> {
+    positions
+        .split(',')
+        .filter_map(|position| position.parse().ok())
+        .collect()
+}
+
+// Poisoning is ignored as readers roll back their transaction when dropped,
+// even during unwinding, and the caches stay consistent in any case.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+fn get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
 }
 
 type Tokenizers = HashMap<String, Box<dyn ErasedTokenizer>>;
@@ -198,22 +928,28 @@ struct Field {
 
 type Fields = HashMap<String, Field>;
 
+/// The `PRAGMA data_version` of a connection and the generation of the index
+/// for which the fields were cached, if any.
+type DataVersion = Option<(i64, u64)>;
+
 fn read_field<'fields>(
     conn: &Connection,
+    schema: &str,
     fields: &'fields mut Fields,
     name: &str,
 ) -> Result<&'fields Field, Error> {
     match fields.entry_ref(name) {
         EntryRef::Occupied(entry) => Ok(entry.into_mut()),
         EntryRef::Vacant(entry) => {
-            let mut stmt = conn.prepare(
+            let mut stmt = conn.prepare(&format!(
                 r#"SELECT
                        canter_fields.id, canter_fields.tokenizer,
-                       COUNT(canter_documents.document_id), AVG(canter_documents.count)
-                   FROM canter_fields LEFT JOIN canter_documents
-                   ON canter_fields.id = canter_documents.field_id
-                   WHERE canter_fields.name = ? GROUP BY canter_fields.id"#,
-            )?;
+                       canter_field_stats.documents,
+                       CAST(canter_field_stats.total AS REAL) / canter_field_stats.documents
+                   FROM {schema}.canter_fields LEFT JOIN {schema}.canter_field_stats
+                   ON canter_fields.id = canter_field_stats.field_id
+                   WHERE canter_fields.name = ?"#
+            ))?;
 
             let field = stmt
                 .query_row(params![name], |row| {
@@ -242,9 +978,30 @@ fn read_field<'fields>(
 
 #[cfg(test)]
 mod tests {
-    use rusqlite::Connection;
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use hashbrown::HashMap;
+    use rusqlite::{Connection, OpenFlags};
 
-    use crate::Index;
+    use crate::{
+        Config, FieldConfig, Index, StoredScoring, bm25, bm25_weighted,
+        error::Error,
+        lock,
+        query::{
+            BoostQuery, CombinedQuery, ConstantScoreQuery, Coordination, Decay, DecayQuery,
+            MatchNoneQuery, Occur, PhraseStrategy, Query, TempTableQuery,
+        },
+        reader::{ClauseResult, Order, Reader, SortKey},
+        tokenizer::{
+            AsciiFolding, CaseFold, Form, HtmlStrip, Language, LimitLength, MinLength, Ngram,
+            Normalize, SentenceBoundary, SplitCamelCase, SplitKeepingDottedNumbers,
+            SplitNonAlphanumeric, Stemmer, StopWords, Synonyms, ToLowerCase, Tokenizer,
+            TruncateLength,
+        },
+        writer::MAX_POSITION,
+    };
 
     #[test]
     fn it_works() {
@@ -320,4 +1077,5035 @@ mod tests {
         let results = reader.search(&*query, Some(1), Some(1), None).unwrap();
         assert_eq!(results, [(2, 0.8317766166719343)]);
     }
+
+    fn sample_index() -> Index {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "FOO bar").unwrap();
+            writer.add_text(1, "field", "BAZ").unwrap();
+
+            writer.add_text(2, "field", "foo").unwrap();
+            writer.add_text(3, "field", "BAR").unwrap();
+            writer.add_text(4, "field", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        index
+    }
+
+    #[test]
+    fn phrase_across_gap() {
+        struct DropThe;
+
+        impl Tokenizer for DropThe {
+            fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+            where
+                F: FnMut(&str, usize) -> Result<(), Error>,
+            {
+                if text == "the" {
+                    return Ok(());
+                }
+
+                f(text, 0)
+            }
+        }
+
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut index = Index::open(conn, Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "drop_the".to_owned(),
+            SplitNonAlphanumeric.chain(DropThe).into(),
+        );
+        index.add_field("field", "drop_the").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "over the fence").unwrap();
+            writer.add_text(2, "field", "over fence").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:\"over the fence\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("field:\"over fence\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn excluded_count() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("+field:bar -field:foo").unwrap();
+        let (results, excluded) = reader.search_with_excluded(&*query, None, None).unwrap();
+        assert_eq!(results, [(3, 0.8317766166719343)]);
+        assert_eq!(excluded, 1);
+
+        let query = reader.parse("field:bar").unwrap();
+        let (_results, excluded) = reader.search_with_excluded(&*query, None, None).unwrap();
+        assert_eq!(excluded, 0);
+    }
+
+    #[test]
+    fn string_document_ids() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut index = Index::open(conn, Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_text_str("0b6c8a3e-5f2d-4c1a-9e7b-3d2f1a0c9b8e", "field", "foo bar")
+                .unwrap();
+            writer
+                .add_text_str("7f4e2d1c-8b3a-4e5f-a6d7-c8b9a0e1f2d3", "field", "bar")
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let results = reader.search_str(&*query, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "0b6c8a3e-5f2d-4c1a-9e7b-3d2f1a0c9b8e");
+
+        let query = reader.parse("field:bar").unwrap();
+        let mut results = reader.search_str(&*query, None, None).unwrap();
+        results.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "0b6c8a3e-5f2d-4c1a-9e7b-3d2f1a0c9b8e");
+        assert_eq!(results[1].0, "7f4e2d1c-8b3a-4e5f-a6d7-c8b9a0e1f2d3");
+    }
+
+    #[test]
+    fn maintained_average() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut index = Index::open(conn, Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        let mut writer = index.rewrite().unwrap();
+
+        writer.add_text(1, "field", "foo bar").unwrap();
+        writer.add_text(1, "field", "baz").unwrap();
+        writer.add_text(2, "field", "foo").unwrap();
+        writer.add_text(3, "field", "foo bar baz qux").unwrap();
+        writer.add_text(4, "field", "bar").unwrap();
+
+        writer.delete_document(3).unwrap();
+        writer.add_text(5, "field", "qux qux").unwrap();
+        writer.delete_document(4).unwrap();
+
+        let maintained = writer
+            .query_row(
+                "SELECT documents, CAST(total AS REAL) / documents FROM canter_field_stats",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, f64>(1)?)),
+            )
+            .unwrap();
+
+        let recomputed = writer
+            .query_row(
+                "SELECT COUNT(*), AVG(count) FROM canter_documents",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, f64>(1)?)),
+            )
+            .unwrap();
+
+        assert_eq!(maintained, (3, 2.0));
+        assert_eq!(maintained, recomputed);
+
+        let count = writer
+            .query_row(
+                "SELECT count FROM canter_terms WHERE value = 'qux'",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn highlight_analysis_mismatch() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut index = Index::open(conn, Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "custom".to_owned(),
+            SplitNonAlphanumeric.chain(ToLowerCase::default()).into(),
+        );
+        index.add_field("field", "custom").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "FOO bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut reader = index.read().unwrap();
+
+            let positions = reader.highlight(1, "field", "foo", "FOO bar").unwrap();
+            assert_eq!(positions, [0]);
+
+            let positions = reader.highlight(1, "field", "qux", "FOO bar").unwrap();
+            assert_eq!(positions, []);
+
+            let positions = reader.highlight(1, "field", "foo foo", "FOO bar").unwrap();
+            assert_eq!(positions, [0]);
+        }
+
+        index.add_tokenizer("custom".to_owned(), SplitNonAlphanumeric.into());
+
+        let mut reader = index.read().unwrap();
+
+        let err = reader.highlight(1, "field", "foo", "FOO bar").unwrap_err();
+        assert!(matches!(err, Error::AnalysisMismatch { term, .. } if term == "foo"));
+    }
+
+    #[test]
+    fn recency_decay() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut index = Index::open(conn, Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo").unwrap();
+            writer.add_timestamp(1, 1_000).unwrap();
+
+            writer.add_text(2, "field", "foo").unwrap();
+            writer.add_timestamp(2, 5_000).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results[0].1, results[1].1);
+
+        let query = DecayQuery::new(query, Decay::Exponential, 6_000, 1_000.0);
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results[0].0, 2);
+        assert_eq!(results[1].0, 1);
+        assert!((results[0].1 / results[1].1 - 16.0).abs() < 1e-9);
+
+        let query = reader.parse("field:foo").unwrap();
+        let query = DecayQuery::new(query, Decay::Gaussian, 6_000, 1_000.0);
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results[0].0, 2);
+        assert_eq!(results[1].0, 1);
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+    }
+
+    #[test]
+    fn query_plan() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let plan = reader.query_plan(&*query).unwrap();
+        assert!(plan.contains("SEARCH main.canter_terms USING"), "{plan}");
+        assert!(!plan.contains("SCAN main.canter_terms"), "{plan}");
+    }
+
+    #[test]
+    fn skip_empty_fields() {
+        for (skip_empty_fields, avg_documents_count) in [(false, 1.0), (true, 2.0)] {
+            let config = Config {
+                skip_empty_fields,
+                ..Default::default()
+            };
+
+            let mut index = Index::in_memory(config).unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo bar").unwrap();
+            writer.add_text(2, "field", "").unwrap();
+
+            let avg = writer
+                .query_row(
+                    "SELECT CAST(total AS REAL) / documents FROM canter_field_stats",
+                    (),
+                    |row| row.get::<_, f64>(0),
+                )
+                .unwrap();
+
+            assert_eq!(avg, avg_documents_count);
+        }
+    }
+
+    #[test]
+    fn phrase_prefix() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "the quick brown fox").unwrap();
+            writer.add_text(2, "field", "quick red bronco").unwrap();
+            writer
+                .add_text(3, "field", "quick bronco and quick brown")
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:\"quick bro*\"").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(document_id, _score)| *document_id);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 3);
+
+        let query = reader.parse("field:\"quick brown\"").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(document_id, _score)| *document_id);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 3);
+
+        let query = reader.parse("field:\"bro*\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let index = Arc::new(sample_index());
+
+        let threads = (0..4)
+            .map(|_| {
+                let index = Arc::clone(&index);
+
+                thread::spawn(move || {
+                    let mut reader = index.read().unwrap();
+
+                    let query = reader.parse("+field:foo +field:bar").unwrap();
+                    reader.search(&*query, None, None, None).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            let results = thread.join().unwrap();
+            assert_eq!(results, [(1, 1.8483924814931874)]);
+        }
+    }
+
+    #[test]
+    fn match_none() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let results = reader.search(&MatchNoneQuery, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let query = CombinedQuery::new([
+            (Occur::Must, MatchNoneQuery.into()),
+            (Occur::Should, reader.parse("field:foo").unwrap()),
+        ]);
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let query = CombinedQuery::new([
+            (Occur::Should, MatchNoneQuery.into()),
+            (Occur::Should, reader.parse("field:foo").unwrap()),
+        ]);
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn bound_field_id() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        for (text, count) in [("field:foo", 2), ("field:\"foo bar\"", 4)] {
+            let query = reader.parse(text).unwrap();
+
+            let mut sql = String::new();
+            let mut params = Vec::new();
+            query.to_sql("main", true, &mut sql, &mut params);
+
+            assert_eq!(sql.matches("canter_terms.field_id = ?").count(), count / 2);
+            assert_eq!(params.len(), count);
+        }
+    }
+
+    #[test]
+    fn wildcard_field() {
+        for max_clauses in [1, 2, 3] {
+            let mut index = Index::in_memory(Config {
+                max_clauses,
+                ..Default::default()
+            })
+            .unwrap();
+
+            index.add_field("title", "default").unwrap();
+            index.add_field("body", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "title", "foo").unwrap();
+                writer.add_text(2, "body", "foo bar").unwrap();
+                writer.add_text(3, "body", "bar").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            // The expansion to both fields counts as two clauses.
+            if max_clauses == 1 {
+                let err = reader.parse("*:foo").err().unwrap();
+                assert!(matches!(err, Error::TooManyClauses(1)));
+                continue;
+            }
+
+            let query = reader.parse("*:foo").unwrap();
+            let mut results = reader.search(&*query, None, None, None).unwrap();
+            results.sort_by_key(|(document_id, _score)| *document_id);
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].0, 1);
+            assert_eq!(results[1].0, 2);
+
+            let result = reader.parse("*:foo body:bar");
+            if max_clauses == 2 {
+                assert!(matches!(result.err().unwrap(), Error::TooManyClauses(2)));
+            } else {
+                result.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn exact_value() {
+        struct StemRunning;
+
+        impl Tokenizer for StemRunning {
+            fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+            where
+                F: FnMut(&str, usize) -> Result<(), Error>,
+            {
+                f(if text == "running" { "run" } else { text }, 0)
+            }
+        }
+
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "stemmed".to_owned(),
+            SplitNonAlphanumeric.chain(StemRunning).into(),
+        );
+        index.add_field("field", "stemmed").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "running").unwrap();
+            writer.add_text(2, "field", "run").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:running").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let query = reader.parse("field:=running").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let query = reader.parse("field:=run").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn search_stream() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar field:baz").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+
+        let mut streamed = Vec::new();
+        reader
+            .search_stream(&*query, None, None, |document_id, score| {
+                streamed.push((document_id, score));
+
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(streamed, results);
+    }
+
+    #[test]
+    fn proximity_boost() {
+        let filler = (1..50).map(|idx| format!("w{idx}")).collect::<Vec<_>>();
+        let filler = filler.join(" ");
+
+        for proximity_boost in [0.0, 1.0] {
+            let mut config = Config::default();
+            config.fields.insert(
+                "field".to_owned(),
+                FieldConfig {
+                    proximity_boost,
+                    ..Default::default()
+                },
+            );
+
+            let mut index = Index::in_memory(config).unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer
+                    .add_text(1, "field", &format!("foo {filler}"))
+                    .unwrap();
+                writer
+                    .add_text(2, "field", &format!("{filler} foo"))
+                    .unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            for (text, position) in [
+                ("field:foo", 50.0),
+                ("field:\"foo w1\" field:\"w49 foo\"", 49.0),
+            ] {
+                let query = reader.parse(text).unwrap();
+                let results = reader.search(&*query, None, None, None).unwrap();
+                assert_eq!(results.len(), 2);
+
+                if proximity_boost == 0.0 {
+                    assert_eq!(results[0].1, results[1].1);
+                } else {
+                    assert_eq!(results[0].0, 1);
+                    let ratio = results[0].1 / results[1].1;
+                    assert!((ratio - 2.0 / (1.0 + 1.0 / position)).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn describe_tokenizer() {
+        let index = Index::in_memory(Default::default()).unwrap();
+
+        assert_eq!(
+            index.describe_tokenizer("default").unwrap(),
+            "SplitNonAlphanumeric → LimitLength(40) → ToLowerCase"
+        );
+        assert_eq!(index.describe_tokenizer("stub").unwrap(), "StubTokenizer");
+        assert_eq!(index.describe_tokenizer("missing"), None);
+    }
+
+    #[test]
+    fn case_fold() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "folded".to_owned(),
+            SplitNonAlphanumeric.chain(CaseFold::default()).into(),
+        );
+        index.add_tokenizer(
+            "turkic".to_owned(),
+            SplitNonAlphanumeric.chain(CaseFold::turkic()).into(),
+        );
+        index.add_field("folded", "folded").unwrap();
+        index.add_field("turkic", "turkic").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "folded", "Straße").unwrap();
+            writer.add_text(2, "turkic", "DİYARBAKIR").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("folded:STRASSE").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        assert_eq!(
+            reader.analyze("turkic", "DİYARBAKIR").unwrap(),
+            ["diyarbakır"]
+        );
+        assert_eq!(
+            reader.analyze("folded", "DİYARBAKIR").unwrap(),
+            ["di\u{307}yarbakir"]
+        );
+
+        let query = reader.parse("turkic:diyarbakır").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn phrase_slop() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "quick brown red fox").unwrap();
+            writer.add_text(2, "field", "brown red quick fox").unwrap();
+            writer.add_text(3, "field", "fox red brown quick").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:\"quick fox\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("field:\"quick fox\"~1").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        // Within the window, the score decreases with the distance from the expected positions.
+        let query = reader.parse("field:\"quick fox\"~2").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, 2);
+        assert_eq!(results[1].0, 1);
+        assert_eq!(results[2].0, 3);
+        assert!((results[0].1 / results[1].1 - 3.0).abs() < 1e-9);
+        assert!((results[0].1 / results[2].1 - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reindex() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer("custom".to_owned(), SplitNonAlphanumeric.into());
+        index.add_field("field", "custom").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_stored_text(1, "field", "FOO bar").unwrap();
+            writer.add_stored_text(1, "field", "BAZ").unwrap();
+            writer.add_stored_text(2, "field", "Foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let search = |index: &Index, text| {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse(text).unwrap();
+            let mut results = reader.search(&*query, None, None, None).unwrap();
+            results.sort_by_key(|(document_id, _score)| *document_id);
+            results
+                .into_iter()
+                .map(|(document_id, _score)| document_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(&index, "field:foo"), []);
+
+        index.add_tokenizer(
+            "custom".to_owned(),
+            SplitNonAlphanumeric.chain(ToLowerCase::default()).into(),
+        );
+
+        assert_eq!(search(&index, "field:foo"), []);
+
+        index.reindex().unwrap();
+
+        assert_eq!(search(&index, "field:foo"), [1, 2]);
+        assert_eq!(search(&index, "field:\"bar baz\""), [1]);
+    }
+
+    #[test]
+    fn reindex_values() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "tags".to_owned(),
+            FieldConfig {
+                store: true,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("tags", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_values(1, "tags", &["foo", "bar"]).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let search = |index: &Index, text| {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse(text).unwrap();
+            reader.search(&*query, None, None, None).unwrap().len()
+        };
+
+        assert_eq!(search(&index, "tags:\"foo bar\""), 0);
+
+        index.reindex().unwrap();
+
+        assert_eq!(search(&index, "tags:\"foo bar\""), 0);
+        assert_eq!(search(&index, "tags:bar"), 1);
+    }
+
+    #[test]
+    fn rollback_failed_document() {
+        struct FailOnBoom;
+
+        impl Tokenizer for FailOnBoom {
+            fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+            where
+                F: FnMut(&str, usize) -> Result<(), Error>,
+            {
+                if text == "boom" {
+                    return Err(Error::InvalidValue(text.to_owned()));
+                }
+
+                f(text, 0)
+            }
+        }
+
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "failing".to_owned(),
+            SplitNonAlphanumeric.chain(FailOnBoom).into(),
+        );
+        index.add_field("field", "failing").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo bar").unwrap();
+            writer.add_text(2, "field", "foo boom").unwrap_err();
+            writer.add_text(3, "field", "foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(document_id, _score)| *document_id);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 3);
+
+        let count = reader
+            .query_row(
+                "SELECT count FROM canter_terms WHERE value = 'foo'",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn temp_table_query() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:baz").unwrap();
+        reader.search(&*query, None, None, Some("stage_1")).unwrap();
+
+        let query = reader.parse("field:bar").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let query = CombinedQuery::new([
+            (
+                Occur::Must,
+                TempTableQuery::new("stage_1".to_owned()).into(),
+            ),
+            (Occur::Must, query),
+        ]);
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results, [(1, 0.46209812037329684)]);
+    }
+
+    #[test]
+    fn vocabulary_size() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.vocabulary_size("field").unwrap(), 3);
+        assert!(matches!(
+            reader.vocabulary_size("missing").unwrap_err(),
+            Error::NoSuchField(_)
+        ));
+    }
+
+    #[test]
+    fn coordination() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        for (coordination, expected) in [
+            (
+                Coordination::Terms,
+                [(1, 4.1588830833596715), (2, 0.8317766166719343)],
+            ),
+            (
+                Coordination::Ratio,
+                [(1, 1.3862943611198906), (2, 0.27725887222397805)],
+            ),
+            (
+                Coordination::Disabled,
+                [(1, 1.3862943611198906), (2, 0.8317766166719343)],
+            ),
+        ] {
+            let clauses = ["field:foo", "field:bar", "field:baz"]
+                .map(|text| (Occur::Should, reader.parse(text).unwrap()));
+            let query = CombinedQuery::new(clauses).with_coordination(coordination);
+
+            let mut results = reader.search(&query, None, None, None).unwrap();
+            results.sort_by_key(|(document_id, _score)| *document_id);
+            assert_eq!(results.len(), 4);
+            assert_eq!(results[..2], expected);
+            assert_eq!(results[2].1, expected[1].1);
+            assert_eq!(results[3].1, expected[1].1);
+        }
+    }
+
+    #[test]
+    fn allowed_fields() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("public", "default").unwrap();
+        index.add_field("private", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "public", "foo").unwrap();
+            writer.add_text(2, "private", "foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        reader.with_allowed_fields(&["public"]);
+
+        let query = reader.parse("public:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+
+        let err = reader.parse("public:foo private:foo").err().unwrap();
+        assert!(matches!(err, Error::FieldNotAllowed(name) if name == "private"));
+
+        let query = reader.parse("*:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+    }
+
+    #[test]
+    fn escaped_wildcard() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "stub").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo*").unwrap();
+            writer.add_text(2, "field", "foobar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo\\*").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [1]);
+
+        let query = reader.parse("field:foo*").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2]);
+
+        let query = reader.parse("field:\"foo\\*\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn standalone_bm25() {
+        let index = sample_index();
+
+        let score = index
+            .connection()
+            .query_row("SELECT canter_bm25(4, 1.25, 2, 1, 2)", (), |row| {
+                row.get::<_, f64>(0)
+            })
+            .unwrap();
+
+        assert_eq!(score, bm25(&Config::default(), 4, 1.25, 2, 1, 2));
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+
+        assert_eq!(results[0], (2, bm25(&Config::default(), 4, 1.5, 2, 1, 1)));
+    }
+
+    #[test]
+    fn explicit_positions() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_tokens(
+                    1,
+                    "field",
+                    &[
+                        ("quick".to_owned(), 0),
+                        ("fast".to_owned(), 0),
+                        ("fox".to_owned(), 2),
+                    ],
+                )
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let count = reader
+            .query_row("SELECT count FROM canter_documents", (), |row| {
+                row.get::<_, usize>(0)
+            })
+            .unwrap();
+        assert_eq!(count, 3);
+
+        let query = reader.parse("field:\"quick fox\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let query = reader.parse("field:\"fast fox\"~1").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn remove_tokenizer() {
+        let mut index = sample_index();
+
+        index.add_tokenizer("unused".to_owned(), SplitNonAlphanumeric.into());
+
+        assert!(index.remove_tokenizer("unused").unwrap().is_some());
+        assert!(index.remove_tokenizer("unused").unwrap().is_none());
+
+        match index.remove_tokenizer("default") {
+            Err(Error::TokenizerInUse { name, fields }) => {
+                assert_eq!(name, "default");
+                assert_eq!(fields, ["field"]);
+            }
+            _ => panic!("tokenizer in use was removed"),
+        }
+
+        assert!(index.describe_tokenizer("default").is_some());
+    }
+
+    #[test]
+    fn default_boost() {
+        let mut index = sample_index();
+
+        let results = {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            reader.search(&*query, None, None, None).unwrap()
+        };
+
+        let config = Config {
+            default_boost: 2.0,
+            ..Default::default()
+        };
+
+        let conn = mem::replace(
+            index.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let index = Index::open(conn, config).unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let boosted_results = reader.search(&*query, None, None, None).unwrap();
+
+        assert_eq!(results.len(), boosted_results.len());
+
+        for ((id, score), (boosted_id, boosted_score)) in results.iter().zip(&boosted_results) {
+            assert_eq!(id, boosted_id);
+            assert!((boosted_score - 2.0 * score).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn no_tokens() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "limited".to_owned(),
+            SplitNonAlphanumeric
+                .chain(LimitLength::new(5))
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "limited").unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        match reader.parse("field:overlong").err().unwrap() {
+            Error::NoTokens {
+                field,
+                value,
+                dropped_by,
+            } => {
+                assert_eq!(field, "field");
+                assert_eq!(value, "overlong");
+                assert_eq!(dropped_by, "LimitLength(5)");
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+
+        match reader.parse("field:\"--\"").err().unwrap() {
+            Error::NoTokens { dropped_by, .. } => {
+                assert_eq!(dropped_by, "SplitNonAlphanumeric");
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn merge_from() {
+        let mut index = sample_index();
+        let mut other = Index::in_memory(Default::default()).unwrap();
+
+        other.add_field("field", "default").unwrap();
+        other.add_field("title", "default").unwrap();
+
+        {
+            let mut writer = other.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo qux").unwrap();
+            writer.add_text(1, "title", "qux").unwrap();
+            writer.add_text(2, "field", "quux").unwrap();
+            writer.add_timestamp(3, 0).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_sort_key(20, 0).unwrap();
+            writer.add_timestamp(30, 0).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let err = index.merge_from(&other, 0).err().unwrap();
+        assert!(matches!(err, Error::DocumentConflict(1)));
+
+        let err = index.merge_from(&other, 19).err().unwrap();
+        assert!(matches!(err, Error::DocumentConflict(20)));
+
+        let err = index.merge_from(&other, 27).err().unwrap();
+        assert!(matches!(err, Error::DocumentConflict(30)));
+
+        index.merge_from(&other, 10).unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2, 11]);
+
+        let query = reader.parse("title:qux").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 11);
+
+        let documents = reader
+            .query_row(
+                "SELECT documents FROM canter_field_stats JOIN canter_fields ON canter_fields.id = field_id WHERE name = 'field'",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(documents, 6);
+    }
+
+    #[test]
+    fn sentence_boundary() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "sentences".to_owned(),
+            SentenceBoundary::default()
+                .chain(SplitNonAlphanumeric)
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "sentences").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_text(1, "field", "This is the end. Start again!")
+                .unwrap();
+            writer
+                .add_text(2, "field", "From end start to end.")
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:\"end start\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("field:\"start again\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+    }
+
+    #[test]
+    fn search_normalized() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        let normalized = reader.search_normalized(&*query, None, None).unwrap();
+
+        assert_eq!(normalized.len(), 3);
+        assert_eq!(normalized[0], (results[0].0, 1.0));
+
+        for ((id, score), (normalized_id, normalized_score)) in results.iter().zip(&normalized) {
+            assert_eq!(id, normalized_id);
+            assert!((normalized_score - score / results[0].1).abs() < 1e-9);
+            assert!(*normalized_score <= 1.0);
+        }
+
+        let normalized = reader.search_normalized(&*query, None, Some(1)).unwrap();
+        assert_eq!(normalized.len(), 2);
+        assert!((normalized[0].1 - results[1].1 / results[0].1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn analysis_limit() {
+        let config = Config {
+            analysis_limit: Some(100),
+            ..Default::default()
+        };
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "FOO bar").unwrap();
+            writer.add_text(1, "field", "BAZ").unwrap();
+
+            writer.add_text(2, "field", "foo").unwrap();
+            writer.add_text(3, "field", "BAR").unwrap();
+            writer.add_text(4, "field", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let analysis_limit = reader
+            .query_row("PRAGMA analysis_limit", (), |row| row.get::<_, usize>(0))
+            .unwrap();
+        assert_eq!(analysis_limit, 100);
+
+        let stats = reader
+            .query_row("SELECT COUNT(*) FROM sqlite_stat1", (), |row| {
+                row.get::<_, usize>(0)
+            })
+            .unwrap();
+        assert_ne!(stats, 0);
+
+        let query = reader.parse("field:foo").unwrap();
+        let plan = reader.query_plan(&*query).unwrap();
+        assert!(plan.contains("SEARCH main.canter_terms USING"), "{plan}");
+    }
+
+    #[test]
+    fn regex_query() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:/ba./").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 3, 4]);
+
+        let query = reader.parse("field:/ba/").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let query = reader.parse("field:/f.*|baz/ -field:bar").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [2, 4]);
+
+        let err = reader.parse("field:/ba(/").err().unwrap();
+        assert!(matches!(err, Error::InvalidRegex(_)));
+
+        let err = reader.parse("field:/ba").err().unwrap();
+        assert!(matches!(err, Error::InvalidRegex(_)));
+    }
+
+    #[test]
+    fn range_query() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("number", "stub").unwrap();
+
+        let value = |document_id: i64| document_id * 7919 - 40_000_000;
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            for document_id in 1..=10_000 {
+                writer
+                    .add_number(document_id, "number", value(document_id))
+                    .unwrap();
+            }
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        for (min, max) in [
+            (-1_000_000, 2_500_000),
+            (-40_000_000, -39_000_000),
+            (i64::MIN, i64::MAX),
+            (0, 0),
+            (value(17), value(17)),
+            (value(42) + 1, value(43) - 1),
+            (5, -5),
+        ] {
+            let query = reader.range_query("number", min, max).unwrap();
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+
+            let expected = (1..=10_000)
+                .filter(|document_id| (min..=max).contains(&value(*document_id)))
+                .collect::<Vec<_>>();
+
+            assert_eq!(results, expected, "{min}..={max}");
+        }
+    }
+
+    #[test]
+    fn expand_accents() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "field".to_owned(),
+            FieldConfig {
+                expand_accents: true,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("field", "default").unwrap();
+        index.add_field("other", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Café au lait").unwrap();
+            writer.add_text(2, "field", "Internet cafe").unwrap();
+            writer.add_text(3, "field", "Cafés").unwrap();
+            writer.add_text(4, "other", "café").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:cafe").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2]);
+
+        let query = reader.parse("field:=cafe").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("other:cafe").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let folded_index = |conn: &Connection| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'canter_terms_folded'",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(folded_index(&reader), 1);
+
+        drop(reader);
+
+        // Without any field expanding accents, the folded index is dropped.
+        let conn = mem::replace(
+            index.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let index = Index::open(conn, Default::default()).unwrap();
+
+        let reader = index.read().unwrap();
+
+        assert_eq!(folded_index(&reader), 0);
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("tags", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_values(1, "tags", &["red apple", "green", "apple pie"])
+                .unwrap();
+            writer.add_text(2, "tags", "green apple").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("tags:\"green apple\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("tags:\"green apple\"~5").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("tags:\"apple pie\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("tags:green").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let tf = reader
+            .query_row(
+                "SELECT COUNT(*) FROM canter_postings JOIN canter_terms ON canter_terms.id = term_id WHERE value = 'apple' AND document_id = 1",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(tf, 2);
+    }
+
+    #[test]
+    fn explain_exclusion() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("+field:foo +field:baz -field:bar").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        let clauses = reader.explain_exclusion(&*query, 2).unwrap();
+        assert_eq!(
+            clauses,
+            [
+                ClauseResult {
+                    occur: Occur::Must,
+                    index: 0,
+                    matches: true,
+                },
+                ClauseResult {
+                    occur: Occur::Must,
+                    index: 1,
+                    matches: false,
+                },
+                ClauseResult {
+                    occur: Occur::MustNot,
+                    index: 0,
+                    matches: false,
+                },
+            ]
+        );
+        assert_eq!(
+            clauses
+                .iter()
+                .map(ClauseResult::excludes)
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        let clauses = reader.explain_exclusion(&*query, 1).unwrap();
+        assert_eq!(
+            clauses
+                .iter()
+                .map(ClauseResult::excludes)
+                .collect::<Vec<_>>(),
+            [false, false, true]
+        );
+
+        let query = reader.parse("field:foo").unwrap();
+        let clauses = reader.explain_exclusion(&*query, 3).unwrap();
+        assert_eq!(clauses.len(), 1);
+        assert_eq!(clauses[0].occur, Occur::Should);
+        assert!(!clauses[0].matches);
+    }
+
+    #[test]
+    fn truncate_length() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "truncated".to_owned(),
+            SplitNonAlphanumeric
+                .chain(TruncateLength::default())
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "truncated").unwrap();
+
+        let token = "x".repeat(60);
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", &token).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.analyze("field", &token).unwrap(), ["x".repeat(40)]);
+
+        let query = reader.parse(&format!("field:{}", "x".repeat(50))).unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let mut tokens = Vec::new();
+        TruncateLength::new(5)
+            .tokenize("äöü", |token, _position| {
+                tokens.push(token.to_owned());
+
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(tokens, ["äö"]);
+    }
+
+    #[test]
+    fn stop_words() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "english".to_owned(),
+            SplitNonAlphanumeric
+                .chain(ToLowerCase::default())
+                .chain(StopWords::english())
+                .into(),
+        );
+        index.add_field("field", "english").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "The end of the world").unwrap();
+            writer.add_text(2, "field", "World end").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "The end of the world").unwrap(),
+            ["end", "world"]
+        );
+
+        let err = reader.parse("field:the").err().unwrap();
+        assert!(matches!(err, Error::NoTokens { .. }));
+
+        let query = reader.parse("field:\"end of a world\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("field:\"end world\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results, []);
+
+        // The reversed order is within reach, but not the gap left by the stop words.
+        let query = reader.parse("field:\"end world\"~1").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("field:\"end world\"~2").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn positions_disabled() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "tags".to_owned(),
+            FieldConfig {
+                positions: false,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("tags", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "tags", "red green red").unwrap();
+            writer.add_text(1, "tags", "red blue").unwrap();
+            writer.add_text(2, "tags", "green").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let postings = reader
+            .query_row("SELECT COUNT(*) FROM canter_postings", (), |row| {
+                row.get::<_, usize>(0)
+            })
+            .unwrap();
+        assert_eq!(postings, 4);
+
+        let query = reader.parse("tags:red").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("tags:green").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let err = reader.parse("tags:\"red green\"").err().unwrap();
+        assert!(matches!(err, Error::PositionsDisabled(field) if field == "tags"));
+    }
+
+    #[test]
+    fn positions_disabled_proximity() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "tags".to_owned(),
+            FieldConfig {
+                positions: false,
+                proximity_boost: 1.0,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("tags", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "tags", "red green").unwrap();
+            writer.add_text(2, "tags", "green").unwrap();
+            writer.add_text(3, "tags", "blue").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        for text in ["tags:red", "tags:re*", "tags:/re+d/"] {
+            let query = reader.parse(text).unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 1);
+            assert!(results[0].1.is_finite() && results[0].1 > 0.0);
+        }
+    }
+
+    #[test]
+    fn stemmer() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "stemmed".to_owned(),
+            SplitNonAlphanumeric
+                .chain(ToLowerCase::default())
+                .chain(Stemmer::new(Language::English))
+                .into(),
+        );
+        index.add_field("field", "stemmed").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Running runs").unwrap();
+            writer.add_text(2, "field", "run").unwrap();
+            writer.add_text(3, "field", "runner").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "Running runs").unwrap(),
+            ["run", "run"]
+        );
+
+        let query = reader.parse("field:running").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 2);
+
+        drop(reader);
+
+        assert_eq!(
+            index.describe_tokenizer("stemmed").unwrap(),
+            "SplitNonAlphanumeric → ToLowerCase → Stemmer(English)"
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.positions("field", "foo", 1).unwrap(), [1]);
+        assert_eq!(reader.positions("field", "baz", 1).unwrap(), [3]);
+        assert_eq!(reader.positions("field", "FOO", 1).unwrap(), []);
+        assert_eq!(reader.positions("field", "foo", 3).unwrap(), []);
+    }
+
+    #[test]
+    fn ascii_folding() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "folded".to_owned(),
+            SplitNonAlphanumeric
+                .chain(AsciiFolding::default())
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "folded").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Café naïve").unwrap();
+            writer.add_text(2, "field", "Straße Ærø").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader
+                .analyze("field", "Café naïve Straße Ærø Ωmega")
+                .unwrap(),
+            ["cafe", "naive", "strasse", "aero", "ωmega"]
+        );
+
+        let query = reader.parse("field:cafe").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("field:strasse").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn phrase_strategy() {
+        let texts = [
+            "quick brown fox jumps over the lazy dog",
+            "the lazy fox and the quick brown dog",
+            "brown fox brown fox quick fox",
+            "dog lazy the over jumps fox brown quick",
+        ];
+
+        let queries = [
+            "field:\"quick brown\"",
+            "field:\"brown fox\"",
+            "field:\"lazy dog\"~2",
+            "field:\"quick fox\"~3",
+            "field:\"brown fo*\"",
+            "field:\"the lazy\" -field:\"quick brown fox\"",
+        ];
+
+        let mut all_results = Vec::new();
+
+        for phrase_strategy in [PhraseStrategy::Joins, PhraseStrategy::Intersection] {
+            let mut config = Config {
+                phrase_strategy,
+                ..Default::default()
+            };
+            config.fields.insert(
+                "field".to_owned(),
+                FieldConfig {
+                    proximity_boost: 1.0,
+                    ..Default::default()
+                },
+            );
+
+            let mut index = Index::in_memory(config).unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                for (document_id, text) in texts.iter().enumerate() {
+                    writer.add_text(document_id as i64, "field", text).unwrap();
+                }
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let results = queries
+                .iter()
+                .map(|query| {
+                    let query = reader.parse(query).unwrap();
+                    reader.search(&*query, None, None, None).unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            all_results.push(results);
+        }
+
+        for (joins, intersection) in all_results[0].iter().zip(&all_results[1]) {
+            assert!(!joins.is_empty());
+            assert_eq!(joins.len(), intersection.len());
+
+            for ((id, score), (other_id, other_score)) in joins.iter().zip(intersection) {
+                assert_eq!(id, other_id);
+                assert!(
+                    (score - other_score).abs() < 1e-9,
+                    "{score} != {other_score}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn add_many() {
+        let texts = ["foo bar", "bar baz", "baz qux", "qux foo"];
+
+        let mut all_results = Vec::new();
+
+        for max_parallelism in [Some(1), Some(3)] {
+            let config = Config {
+                max_parallelism,
+                ..Default::default()
+            };
+
+            let mut index = Index::in_memory(config).unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                let workers = Mutex::new(Vec::new());
+
+                writer
+                    .add_many(|worker, parallelism, sink| {
+                        workers
+                            .lock()
+                            .unwrap()
+                            .push((parallelism, thread::current().id()));
+
+                        for (document_id, text) in texts.iter().enumerate() {
+                            if document_id % parallelism == worker
+                                && !sink(document_id as i64, "field".to_owned(), (*text).to_owned())
+                            {
+                                return;
+                            }
+                        }
+                    })
+                    .unwrap();
+
+                let workers = workers.into_inner().unwrap();
+
+                if max_parallelism == Some(1) {
+                    assert_eq!(workers, [(1, thread::current().id())]);
+                }
+
+                for (parallelism, id) in workers {
+                    assert_eq!(parallelism == 1, id == thread::current().id());
+                }
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            assert_eq!(results, [0, 3]);
+
+            assert_eq!(reader.vocabulary_size("field").unwrap(), 4);
+
+            let query = reader.parse("field:foo field:baz").unwrap();
+            let mut results = reader.search(&*query, None, None, None).unwrap();
+            results.sort_by_key(|(id, _)| *id);
+            all_results.push(results);
+        }
+
+        assert_eq!(all_results[0], all_results[1]);
+    }
+
+    #[test]
+    fn normalize() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        // Normalizing first keeps combining marks from being split off as non-alphanumeric.
+        index.add_tokenizer(
+            "normalized".to_owned(),
+            Normalize::new(Form::Nfc)
+                .chain(SplitNonAlphanumeric)
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "normalized").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Caf\u{e9}").unwrap();
+            writer.add_text(2, "field", "Cafe\u{301}").unwrap();
+            writer.add_text(3, "field", "Cafe").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.vocabulary_size("field").unwrap(), 2);
+
+        for query in ["field:cafe\u{301}", "field:caf\u{e9}"] {
+            let query = reader.parse(query).unwrap();
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            assert_eq!(results, [1, 2]);
+        }
+
+        drop(reader);
+
+        assert_eq!(
+            index.describe_tokenizer("normalized").unwrap(),
+            "Normalize(Nfc) → SplitNonAlphanumeric → ToLowerCase"
+        );
+    }
+
+    #[test]
+    fn ngram() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "trigrams".to_owned(),
+            SplitNonAlphanumeric
+                .chain(ToLowerCase::default())
+                .chain(Ngram::new(3))
+                .into(),
+        );
+        index.add_field("field", "trigrams").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Hello world").unwrap();
+            writer.add_text(2, "field", "Yellow").unwrap();
+            writer.add_text(3, "field", "Grüße").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "Hello Grüße ok").unwrap(),
+            ["hel", "ell", "llo", "grü", "rüß", "üße", "ok"]
+        );
+
+        let query = reader.parse("field:ello").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2]);
+
+        let query = reader.parse("field:hello").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("field:üße").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 3);
+    }
+
+    #[test]
+    fn max_term_frequency() {
+        let mut scores = Vec::new();
+
+        for max_term_frequency in [Some(3), None] {
+            let config = Config {
+                max_term_frequency,
+                ..Default::default()
+            };
+
+            let mut index = Index::in_memory(config).unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", &"spam ".repeat(1000)).unwrap();
+
+                writer.add_text(2, "field", "spam spam spam").unwrap();
+                for idx in 0..997 {
+                    writer
+                        .add_text(2, "field", &format!("filler{idx}"))
+                        .unwrap();
+                }
+
+                for document_id in 3..1200 {
+                    writer.add_text(document_id, "field", "other").unwrap();
+                }
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:spam").unwrap();
+            let mut results = reader.search(&*query, None, None, None).unwrap();
+            results.sort_by_key(|(id, _)| *id);
+
+            assert_eq!(results.len(), 2);
+            assert!(results[1].1 > 0.0);
+
+            scores.push((results[0].1, results[1].1));
+        }
+
+        let (capped_spam, capped_other) = scores[0];
+        assert!((capped_spam - capped_other).abs() < 1e-9);
+
+        let (spam, other) = scores[1];
+        assert!(spam > other);
+    }
+
+    #[test]
+    fn search_faceted() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+        index.add_field("tags", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "FOO bar").unwrap();
+            writer.add_text(1, "field", "BAZ").unwrap();
+            writer.add_text(1, "tags", "red green").unwrap();
+
+            writer.add_text(2, "field", "foo").unwrap();
+            writer.add_text(2, "tags", "red").unwrap();
+
+            writer.add_text(3, "field", "BAR").unwrap();
+            writer.add_text(3, "tags", "blue red").unwrap();
+
+            writer.add_text(4, "field", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar").unwrap();
+
+        let (results, facets) = reader
+            .search_faceted(&*query, Some(2), None, &["tags", "field"])
+            .unwrap();
+
+        assert_eq!(
+            results,
+            reader.search(&*query, Some(2), None, None).unwrap()
+        );
+
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facets["tags"], reader.facet(&*query, "tags").unwrap());
+        assert_eq!(facets["field"], reader.facet(&*query, "field").unwrap());
+        assert_eq!(
+            facets["tags"],
+            [
+                ("red".to_owned(), 3),
+                ("blue".to_owned(), 1),
+                ("green".to_owned(), 1)
+            ]
+        );
+
+        let (results, _facets) = reader.search_faceted(&*query, None, None, &[]).unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn split_camel_case() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "identifiers".to_owned(),
+            SplitNonAlphanumeric
+                .chain(SplitCamelCase)
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "identifiers").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "getUserName").unwrap();
+            writer.add_text(2, "field", "HTTPServer").unwrap();
+            writer.add_text(3, "field", "parseHTTP2Response").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "getUserName").unwrap(),
+            ["get", "user", "name"]
+        );
+        assert_eq!(
+            reader.analyze("field", "HTTPServer").unwrap(),
+            ["http", "server"]
+        );
+        assert_eq!(
+            reader.analyze("field", "parseHTTP2Response").unwrap(),
+            ["parse", "http", "2", "response"]
+        );
+        assert_eq!(
+            reader.analyze("field", "utf8_decode IOError x").unwrap(),
+            ["utf", "8", "decode", "io", "error", "x"]
+        );
+        assert_eq!(
+            reader.analyze("field", "lowercase UPPERCASE").unwrap(),
+            ["lowercase", "uppercase"]
+        );
+
+        let query = reader.parse("field:user").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("field:http").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [2, 3]);
+
+        let query = reader.parse("field:\"user name\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+    }
+
+    #[test]
+    fn document_range() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar field:baz").unwrap();
+
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2, 3, 4]);
+
+        reader.with_document_range(2, 3);
+
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [2, 3]);
+
+        let results = reader.search(&*query, Some(1), Some(1), None).unwrap();
+        assert_eq!(results.len(), 1);
+
+        let (results, facets) = reader
+            .search_faceted(&*query, None, None, &["field"])
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            facets["field"],
+            [("bar".to_owned(), 1), ("foo".to_owned(), 1)]
+        );
+        assert_eq!(facets["field"], reader.facet(&*query, "field").unwrap());
+    }
+
+    #[test]
+    fn dotted_field_names() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("meta.author", "default").unwrap();
+        index.add_field("meta.title", "default").unwrap();
+
+        for name in ["", "*", "meta:author", "meta author", "+meta", "-meta"] {
+            assert!(matches!(
+                index.add_field(name, "default"),
+                Err(Error::InvalidFieldName(invalid)) if invalid == name
+            ));
+        }
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "meta.author", "Jane Doe").unwrap();
+            writer.add_text(1, "meta.title", "Foo").unwrap();
+            writer.add_text(2, "meta.author", "John Doe").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("meta.author:jane").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("+meta.author:doe -meta.title:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn synonyms() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        let mut synonyms = HashMap::new();
+        synonyms.insert(
+            "car".to_owned(),
+            vec!["automobile".to_owned(), "car".to_owned()],
+        );
+        synonyms.insert("automobile".to_owned(), vec!["car".to_owned()]);
+
+        index.add_tokenizer(
+            "synonyms".to_owned(),
+            SplitNonAlphanumeric
+                .chain(ToLowerCase::default())
+                .chain(Synonyms::new(synonyms))
+                .into(),
+        );
+        index.add_field("field", "synonyms").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "red car parked").unwrap();
+            writer.add_text(2, "field", "red automobile").unwrap();
+            writer.add_text(3, "field", "red bicycle").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "red car").unwrap(),
+            ["red", "car", "automobile"]
+        );
+
+        assert_eq!(reader.positions("field", "car", 1).unwrap(), [2]);
+        assert_eq!(reader.positions("field", "automobile", 1).unwrap(), [2]);
+        assert_eq!(reader.positions("field", "parked", 1).unwrap(), [3]);
+
+        let length = reader
+            .query_row(
+                "SELECT count FROM canter_documents WHERE document_id = 1",
+                (),
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap();
+        assert_eq!(length, 3);
+
+        for text in ["field:car", "field:automobile", "field:\"red car\""] {
+            let query = reader.parse(text).unwrap();
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            assert_eq!(results, [1, 2], "{text}");
+        }
+
+        let query = reader.parse("field:\"car parked\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+    }
+
+    #[test]
+    fn min_length() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "min_length".to_owned(),
+            SplitNonAlphanumeric
+                .chain(MinLength::new(3))
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "min_length").unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.analyze("field", "a an the cat").unwrap(),
+            ["the", "cat"]
+        );
+
+        assert_eq!(reader.analyze("field", "öß Öße").unwrap(), ["öße"]);
+
+        match reader.parse("field:an").err().unwrap() {
+            Error::NoTokens { dropped_by, .. } => {
+                assert_eq!(dropped_by, "MinLength(3)");
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn search_with_total() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar field:baz").unwrap();
+
+        let all_results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(all_results.len(), 4);
+
+        let (results, total) = reader.search_with_total(&*query, Some(2), Some(1)).unwrap();
+        assert_eq!(results, all_results[1..3]);
+        assert_eq!(total, 4);
+
+        let (results, total) = reader
+            .search_with_total(&*query, Some(2), Some(10))
+            .unwrap();
+        assert!(results.is_empty());
+        assert_eq!(total, 4);
+
+        let query = reader.parse("+field:foo -field:bar").unwrap();
+
+        let (results, total) = reader.search_with_total(&*query, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(total, 1);
+
+        let query = reader.parse("+field:foo +field:baz +field:bar").unwrap();
+
+        let (results, total) = reader.search_with_total(&*query, None, Some(1)).unwrap();
+        assert!(results.is_empty());
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn bm25_idf_smoothing() {
+        let config = Config {
+            bm25_idf_numerator_smoothing: 2.0,
+            bm25_idf_denominator_smoothing: 0.0,
+            bm25_idf_offset: 1.0,
+            stored_scoring: StoredScoring::Overwrite,
+            ..Default::default()
+        };
+
+        // With `doc_len` equal to `avg_len`, the score of a single occurrence is just the idf.
+        assert!((bm25(&Default::default(), 4, 1.0, 2, 1, 1) - 2.0_f64.ln()).abs() < 1e-9);
+        assert!((bm25(&config, 4, 1.0, 2, 1, 1) - 3.0_f64.ln()).abs() < 1e-9);
+
+        let mut index = sample_index();
+
+        let mut scores = Vec::new();
+
+        for config in [Config::default(), config] {
+            let conn = mem::replace(
+                index.connection_mut(),
+                Connection::open_in_memory().unwrap(),
+            );
+            index = Index::open(conn, config).unwrap();
+
+            let reader = index.read().unwrap();
+
+            let score = reader
+                .query_row("SELECT canter_bm25(4, 1.0, 2, 1, 1)", (), |row| {
+                    row.get::<_, f64>(0)
+                })
+                .unwrap();
+            scores.push(score);
+        }
+
+        assert!((scores[0] - 2.0_f64.ln()).abs() < 1e-9);
+        assert!((scores[1] - 3.0_f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn html_strip() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "html".to_owned(),
+            HtmlStrip::default()
+                .chain(SplitNonAlphanumeric)
+                .chain(ToLowerCase::default())
+                .into(),
+        );
+        index.add_field("field", "html").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_text(
+                    1,
+                    "field",
+                    r#"<p class="intro">Fish&nbsp;&amp;&nbsp;Chips</p><br/>Caf&eacute;"#,
+                )
+                .unwrap();
+            writer.add_text(2, "field", "<div>paragraph</div>").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader
+                .analyze(
+                    "field",
+                    r#"<p class="intro">Fish&nbsp;&amp;&nbsp;Chips</p><br/>Caf&eacute;"#
+                )
+                .unwrap(),
+            ["fish", "chips", "café"]
+        );
+        assert_eq!(
+            reader
+                .analyze("field", "a<b>b</b>c &#65;&#x42; &lt;i&gt;")
+                .unwrap(),
+            ["a", "b", "c", "ab", "i"]
+        );
+        assert_eq!(
+            reader
+                .analyze(
+                    "field",
+                    "<!-- hidden <b>x</b> -->shown<SCRIPT>var y;</script>text<style>p {}</style>"
+                )
+                .unwrap(),
+            ["shown", "text"]
+        );
+        assert_eq!(
+            reader.analyze("field", "left <unclosed right").unwrap(),
+            ["left", "unclosed", "right"]
+        );
+        assert_eq!(
+            reader
+                .analyze("field", "&hellip;&NotNestedGreaterGreater;ok&bogus;")
+                .unwrap(),
+            ["ok", "bogus"]
+        );
+
+        let query = reader.parse("field:p").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert!(results.is_empty());
+
+        let query = reader.parse("field:paragraph").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn analyze_batch() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let texts = ["FOO bar", "", "Baz, qux!", "--"];
+
+        let tokens = reader.analyze_batch("field", &texts).unwrap();
+
+        assert_eq!(tokens.len(), texts.len());
+        for (text, tokens) in texts.iter().zip(&tokens) {
+            assert_eq!(*tokens, reader.analyze("field", text).unwrap());
+        }
+        assert_eq!(tokens[2], ["baz", "qux"]);
+
+        assert!(matches!(
+            reader.analyze_batch("missing", &texts),
+            Err(Error::NoSuchField(_))
+        ));
+    }
+
+    #[test]
+    fn copy_to() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "body".to_owned(),
+            FieldConfig {
+                copy_to: vec!["all".to_owned()],
+                ..Default::default()
+            },
+        );
+        config.fields.insert(
+            "title".to_owned(),
+            FieldConfig {
+                copy_to: vec!["all".to_owned()],
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_tokenizer(
+            "keyword".to_owned(),
+            SplitNonAlphanumeric.chain(StopWords::english()).into(),
+        );
+        index.add_field("body", "default").unwrap();
+        index.add_field("title", "default").unwrap();
+        index.add_field("all", "keyword").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "title", "Foo").unwrap();
+            writer.add_text(1, "body", "the Bar").unwrap();
+            writer.add_text(2, "body", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("all:Bar").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("all:\"Foo Bar\"~1").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        assert_eq!(reader.positions("all", "Foo", 1).unwrap(), [1]);
+        assert_eq!(reader.positions("all", "Bar", 1).unwrap(), [3]);
+        assert!(reader.positions("all", "bar", 1).unwrap().is_empty());
+        assert_eq!(reader.positions("body", "bar", 1).unwrap(), [2]);
+
+        let query = reader.parse("body:foo").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn update_and_delete() {
+        let mut index = sample_index();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.delete_document(1).unwrap();
+            writer.add_text(5, "field", "qux foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [2, 5]);
+
+        let query = reader.parse("field:baz").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 4);
+
+        let maintained = reader
+            .query_row(
+                "SELECT documents, total FROM canter_field_stats",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)),
+            )
+            .unwrap();
+
+        let recomputed = reader
+            .query_row(
+                "SELECT COUNT(*), SUM(count) FROM canter_documents",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)),
+            )
+            .unwrap();
+
+        assert_eq!(maintained, (4, 5));
+        assert_eq!(maintained, recomputed);
+
+        let inconsistent = reader
+            .query_row(
+                r#"SELECT COUNT(*) FROM canter_terms
+                   WHERE count != (SELECT COUNT(*) FROM canter_postings WHERE term_id = canter_terms.id)"#,
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(inconsistent, 0);
+
+        let unused = reader
+            .query_row(
+                "SELECT COUNT(*) FROM canter_terms WHERE count = 0",
+                (),
+                |row| row.get::<_, usize>(0),
+            )
+            .unwrap();
+        assert_eq!(unused, 0);
+    }
+
+    #[test]
+    fn too_many_tokens() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "presence".to_owned(),
+            FieldConfig {
+                positions: false,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("field", "default").unwrap();
+        index.add_field("presence", "default").unwrap();
+
+        let mut writer = index.rewrite().unwrap();
+
+        writer
+            .add_tokens(1, "field", &[("foo".to_owned(), MAX_POSITION - 1)])
+            .unwrap();
+
+        for field in ["field", "presence"] {
+            assert!(matches!(
+                writer.add_tokens(1, field, &[("bar".to_owned(), MAX_POSITION)]),
+                Err(Error::TooManyTokens(1))
+            ));
+        }
+
+        assert!(matches!(
+            writer.add_tokens(2, "field", &[("bar".to_owned(), usize::MAX)]),
+            Err(Error::TooManyTokens(2))
+        ));
+
+        assert!(matches!(
+            writer.add_text(1, "field", "bar"),
+            Err(Error::TooManyTokens(1))
+        ));
+
+        writer.commit().unwrap();
+
+        let reader = index.read().unwrap();
+
+        let (documents, total) = reader
+            .query_row(
+                "SELECT documents, total FROM canter_field_stats",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)),
+            )
+            .unwrap();
+        assert_eq!(documents, 1);
+        assert_eq!(total, MAX_POSITION);
+    }
+
+    #[test]
+    fn update_replaces_fields() {
+        let mut index = sample_index();
+
+        index.add_field("title", "default").unwrap();
+
+        for _ in 0..2 {
+            let mut writer = index.update().unwrap();
+
+            writer.add_text(1, "field", "foo").unwrap();
+            writer.add_text(1, "field", "qux").unwrap();
+            writer.add_stored_text(1, "title", "Title").unwrap();
+            writer.add_text(5, "field", "foo foo").unwrap();
+
+            assert!(matches!(
+                writer.add_text(3, "missing", "bar"),
+                Err(Error::NoSuchField(_))
+            ));
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.positions("field", "foo", 1).unwrap(), [1]);
+        assert_eq!(reader.positions("field", "qux", 1).unwrap(), [2]);
+        assert!(reader.positions("field", "bar", 1).unwrap().is_empty());
+        assert_eq!(reader.positions("field", "bar", 3).unwrap(), [1]);
+        assert_eq!(reader.positions("field", "foo", 5).unwrap(), [1, 2]);
+
+        let query = reader.parse("field:baz").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 4);
+
+        let terms = reader
+            .prepare("SELECT value, count FROM canter_terms ORDER BY value")
+            .unwrap()
+            .query_map((), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            terms,
+            [
+                ("bar".to_owned(), 1),
+                ("baz".to_owned(), 1),
+                ("foo".to_owned(), 4),
+                ("qux".to_owned(), 1),
+                ("title".to_owned(), 1),
+            ]
+        );
+
+        let maintained = reader
+            .prepare("SELECT documents, total FROM canter_field_stats ORDER BY field_id")
+            .unwrap()
+            .query_map((), |row| {
+                Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let recomputed = reader
+            .prepare("SELECT COUNT(*), SUM(count) FROM canter_documents GROUP BY field_id ORDER BY field_id")
+            .unwrap()
+            .query_map((), |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(maintained, [(5, 7), (1, 1)]);
+        assert_eq!(maintained, recomputed);
+
+        let stored = reader
+            .query_row("SELECT COUNT(*) FROM canter_stored", (), |row| {
+                row.get::<_, usize>(0)
+            })
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[test]
+    fn delete_field() {
+        let mut index = sample_index();
+
+        index.add_field("title", "default").unwrap();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_stored_text(1, "title", "foo").unwrap();
+            writer.add_text(5, "title", "bar baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.delete_field("title").unwrap();
+
+            assert!(matches!(
+                writer.delete_field("title"),
+                Err(Error::NoSuchField(_))
+            ));
+            assert!(matches!(
+                writer.add_text(1, "title", "foo"),
+                Err(Error::NoSuchField(_))
+            ));
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert!(matches!(
+            reader.parse("title:foo"),
+            Err(Error::NoSuchField(_))
+        ));
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [1, 2]);
+
+        for table in [
+            "canter_fields",
+            "canter_terms",
+            "canter_postings",
+            "canter_documents",
+            "canter_field_stats",
+            "canter_stored",
+        ] {
+            let count = reader
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), (), |row| {
+                    row.get::<_, usize>(0)
+                })
+                .unwrap();
+
+            let expected = match table {
+                "canter_fields" | "canter_field_stats" => 1,
+                "canter_terms" => 3,
+                "canter_postings" => 6,
+                "canter_documents" => 4,
+                _ => 0,
+            };
+
+            assert_eq!(count, expected, "{table}");
+        }
+    }
+
+    #[test]
+    fn delete_field_attached_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("ATTACH DATABASE ':memory:' AS aux", ())
+            .unwrap();
+
+        let mut other = Index::open(conn, Default::default()).unwrap();
+
+        other.add_field("f", "default").unwrap();
+
+        {
+            let mut writer = other.rewrite().unwrap();
+
+            writer.add_stored_text(1, "f", "foo bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let conn = mem::replace(
+            other.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let config = Config {
+            schema: "aux".to_owned(),
+            ..Default::default()
+        };
+        let mut index = Index::open(conn, config).unwrap();
+
+        index.add_field("f", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_stored_text(2, "f", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.delete_field("f").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let reader = index.read().unwrap();
+
+        for table in [
+            "canter_fields",
+            "canter_terms",
+            "canter_postings",
+            "canter_documents",
+            "canter_field_stats",
+            "canter_stored",
+        ] {
+            let count = |schema: &str| {
+                reader
+                    .query_row(
+                        &format!("SELECT COUNT(*) FROM {schema}.{table}"),
+                        (),
+                        |row| row.get::<_, usize>(0),
+                    )
+                    .unwrap()
+            };
+
+            let expected = match table {
+                "canter_terms" | "canter_postings" => 2,
+                _ => 1,
+            };
+
+            assert_eq!(count("main"), expected, "{table}");
+            assert_eq!(count("aux"), 0, "{table}");
+        }
+    }
+
+    #[test]
+    fn parse_multi_field() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_tokenizer(
+            "stemmed".to_owned(),
+            SplitNonAlphanumeric
+                .chain(ToLowerCase::default())
+                .chain(Stemmer::new(Language::English))
+                .into(),
+        );
+        index.add_field("stemmed", "stemmed").unwrap();
+        index.add_field("plain", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "stemmed", "running shoes").unwrap();
+            writer.add_text(2, "plain", "runs daily").unwrap();
+            writer.add_text(3, "plain", "running late").unwrap();
+            writer.add_text(4, "stemmed", "walking").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let search = |reader: &mut Reader, fields: &[&str], value: &str| {
+            let query = reader.parse_multi_field(fields, value).unwrap();
+
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+
+        assert_eq!(search(&mut reader, &["stemmed", "plain"], "runs"), [1, 2]);
+        assert_eq!(
+            search(&mut reader, &["stemmed", "plain"], "running"),
+            [1, 3]
+        );
+        assert_eq!(search(&mut reader, &["plain"], "running"), [3]);
+
+        assert!(matches!(
+            reader.parse_multi_field(&["stemmed", "plain"], "--"),
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(
+            reader.parse_multi_field(&["missing"], "runs"),
+            Err(Error::NoSuchField(_))
+        ));
+    }
+
+    #[test]
+    fn multi_field_coordination() {
+        let mut scores = Vec::new();
+
+        for coordination in [Coordination::Terms, Coordination::Disabled] {
+            let mut index = Index::in_memory(Config {
+                coordination,
+                ..Default::default()
+            })
+            .unwrap();
+
+            index.add_field("title", "default").unwrap();
+            index.add_field("body", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "title", "foo").unwrap();
+                writer.add_text(1, "body", "foo bar").unwrap();
+                writer.add_text(2, "body", "bar").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse_multi_field(&["title", "body"], "foo").unwrap();
+            let multi_field = reader.search(&*query, None, None, None).unwrap();
+
+            let query = reader.parse("*:foo").unwrap();
+            let wildcard = reader.search(&*query, None, None, None).unwrap();
+
+            assert_eq!(multi_field, wildcard);
+            assert_eq!(multi_field.len(), 1);
+            scores.push(multi_field[0].1);
+        }
+
+        // Both fields match, so counting the matched clauses doubles the score.
+        assert!((scores[0] / scores[1] - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fuzzy_query() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "color").unwrap();
+            writer.add_text(2, "field", "colour colors").unwrap();
+            writer.add_text(3, "field", "cooler").unwrap();
+            writer.add_text(4, "field", "collar").unwrap();
+            writer.add_text(5, "field", "café").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let search = |reader: &mut Reader, value: &str, max_distance: usize| {
+            let query = reader.fuzzy_query("field", value, max_distance).unwrap();
+
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+
+        assert_eq!(search(&mut reader, "color", 0), [1]);
+        assert_eq!(search(&mut reader, "color", 1), [1, 2]);
+        assert_eq!(search(&mut reader, "color", 2), [1, 2, 3, 4]);
+        assert_eq!(search(&mut reader, "color", 5), [1, 2, 3, 4]);
+        assert_eq!(search(&mut reader, "cafe", 1), [5]);
+
+        // Document 2 matches both `colour` and `colors`.
+        let query = reader.fuzzy_query("field", "color", 1).unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("+field:colour").unwrap();
+        let fuzzy = reader.fuzzy_query("field", "colrs", 1).unwrap();
+        let combined: Box<dyn Query> =
+            CombinedQuery::new(vec![(Occur::Must, query), (Occur::Must, fuzzy)]).into();
+        let results = reader.search(&*combined, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        let distance = reader
+            .query_row(
+                "SELECT canter_edit_distance('kitten', 'sitting', 5), canter_edit_distance('kitten', 'sitting', 2)",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)),
+            )
+            .unwrap();
+        assert_eq!(distance, (3, 3));
+    }
+
+    #[test]
+    fn external_changes() {
+        let index = sample_index();
+
+        let score = |index: &Index| {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            let results = reader.search(&*query, Some(1), None, None).unwrap();
+
+            results[0].1
+        };
+
+        let before = score(&index);
+        assert_eq!(score(&index), before);
+
+        index
+            .connection()
+            .execute(
+                "UPDATE canter_field_stats SET documents = documents * 10, total = total * 10",
+                (),
+            )
+            .unwrap();
+
+        let after = score(&index);
+        assert!(after > before);
+    }
+
+    #[test]
+    fn external_changes_other_connection() {
+        // Databases of the `memdb` VFS whose names start with `/` are shared by all connections
+        // of the process and removed when the last one is closed.
+        let open = || {
+            Connection::open_with_flags(
+                "file:/canter-external-changes?vfs=memdb",
+                OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+            )
+            .unwrap()
+        };
+
+        let mut index = Index::open(open(), Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo").unwrap();
+            writer.add_text(2, "field", "bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let score = |index: &Index| {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            reader.search(&*query, None, None, None).unwrap()[0].1
+        };
+
+        let before = score(&index);
+
+        open()
+            .execute(
+                "UPDATE canter_field_stats SET documents = 100, total = 100",
+                (),
+            )
+            .unwrap();
+
+        let after = score(&index);
+        assert!(after > before);
+    }
+
+    #[test]
+    fn prefix_query() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+        index.add_field("other", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "canter").unwrap();
+            writer.add_text(2, "field", "candy canes").unwrap();
+            writer.add_text(3, "field", "can").unwrap();
+            writer.add_text(4, "field", "cab").unwrap();
+            writer.add_text(4, "other", "candle").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:can*").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        let mut ids = results.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [1, 2, 3]);
+
+        // Document 2 matches two terms whose scores are summed.
+        assert_eq!(results[0].0, 2);
+
+        let query = reader.parse("field:CANT*").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+
+        let query = reader.parse("+field:can* -field:candy").unwrap();
+        let mut ids = reader
+            .search(&*query, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [1, 3]);
+
+        let query = reader.parse("field:can*").unwrap();
+        let plan = reader.query_plan(&*query).unwrap();
+        assert!(!plan.contains("SCAN main.canter_terms"), "{plan}");
+    }
+
+    #[test]
+    fn search_per_field() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("title", "default").unwrap();
+        index.add_field("body", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "title", "foo").unwrap();
+            writer.add_text(1, "body", "foo bar").unwrap();
+            writer.add_text(2, "body", "foo").unwrap();
+            writer.add_text(3, "title", "bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("*:foo").unwrap();
+
+        let merged = reader.search(&*query, None, None, None).unwrap();
+        let mut merged_ids = merged.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        merged_ids.sort();
+        assert_eq!(merged_ids, [1, 2]);
+
+        let mut split = reader.search_per_field(&*query, None, None).unwrap();
+        split.sort_by(|lhs, rhs| (lhs.0, &lhs.1).cmp(&(rhs.0, &rhs.1)));
+        assert_eq!(
+            split
+                .iter()
+                .map(|(id, field, _)| (*id, field.as_str()))
+                .collect::<Vec<_>>(),
+            [(1, "body"), (1, "title"), (2, "body")]
+        );
+
+        // Without coordination, the per-field scores add up to the merged score.
+        let query: Box<dyn Query> = CombinedQuery::new(vec![
+            (Occur::Should, reader.parse("title:foo").unwrap()),
+            (Occur::Should, reader.parse("body:foo").unwrap()),
+        ])
+        .with_coordination(Coordination::Disabled)
+        .into();
+
+        let merged = reader.search(&*query, None, None, None).unwrap();
+        let split = reader.search_per_field(&*query, None, None).unwrap();
+        assert_eq!(split.len(), 3);
+
+        for (id, score) in merged {
+            let split_score = split
+                .iter()
+                .filter(|(split_id, _, _)| *split_id == id)
+                .map(|(_, _, score)| score)
+                .sum::<f64>();
+            assert!((score - split_score).abs() < 1e-9);
+        }
+
+        let query = reader.parse("+*:foo -title:foo").unwrap();
+        let split = reader.search_per_field(&*query, None, None).unwrap();
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].0, 2);
+        assert_eq!(split[0].1, "body");
+
+        let query = reader.parse("*:foo").unwrap();
+        let split = reader.search_per_field(&*query, Some(1), Some(1)).unwrap();
+        assert_eq!(split.len(), 1);
+    }
+
+    #[test]
+    fn exists_query() {
+        let mut index = sample_index();
+
+        index.add_field("title", "default").unwrap();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_text(2, "title", "foo").unwrap();
+            writer.add_text(4, "title", "bar").unwrap();
+            writer.add_text(5, "title", "baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let search = |reader: &Reader, query: &dyn Query| {
+            let mut results = reader
+                .search(query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+
+        let query = reader.exists_query("title").unwrap();
+        assert_eq!(search(&reader, &*query), [2, 4, 5]);
+
+        let query = reader.exists_query("field").unwrap();
+        assert_eq!(search(&reader, &*query), [1, 2, 3, 4]);
+
+        let query: Box<dyn Query> = CombinedQuery::new(vec![
+            (Occur::Must, reader.exists_query("field").unwrap()),
+            (Occur::MustNot, reader.exists_query("title").unwrap()),
+        ])
+        .into();
+        assert_eq!(search(&reader, &*query), [1, 3]);
+    }
+
+    #[test]
+    fn wildcard_query() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "stub").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "test").unwrap();
+            writer.add_text(2, "field", "text").unwrap();
+            writer.add_text(3, "field", "testing").unwrap();
+            writer.add_text(4, "field", "toast").unwrap();
+            writer.add_text(5, "field", "te?t").unwrap();
+            writer.add_text(6, "field", "te*t[1]").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let search = |reader: &mut Reader, text: &str| {
+            let query = reader.parse(text).unwrap();
+
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+
+        assert_eq!(search(&mut reader, "field:te?t"), [1, 2, 5]);
+        assert_eq!(search(&mut reader, "field:te?t*"), [1, 2, 3, 5, 6]);
+        assert_eq!(search(&mut reader, "field:t*st"), [1, 4]);
+        assert_eq!(search(&mut reader, "field:te\\?t"), [5]);
+        assert_eq!(search(&mut reader, "field:te\\?*"), [5]);
+        assert_eq!(search(&mut reader, "field:te\\*t[1]"), [6]);
+        assert_eq!(search(&mut reader, "field:te\\*t?1?"), [6]);
+
+        // A single trailing `*` remains a prefix query and quoted values do not contain wildcards.
+        assert_eq!(search(&mut reader, "field:tes*"), [1, 3]);
+        assert_eq!(search(&mut reader, "field:\"te?t\""), [5]);
+    }
+
+    #[test]
+    fn wildcard_case_normalization() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "Hello World").unwrap();
+            writer.add_text(2, "field", "hallo welt").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let mut search = |text: &str| {
+            let query = reader.parse(text).unwrap();
+
+            let mut results = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+
+        assert_eq!(search("field:H?llo"), [1, 2]);
+        assert_eq!(search("field:HE*O"), [1]);
+        assert_eq!(search("field:W*L*"), [1, 2]);
+        assert_eq!(search("field:Hello?"), [1]);
+        assert_eq!(search("field:=H?llo"), []);
+    }
+
+    #[test]
+    fn phrase_slop_any_order() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo bar").unwrap();
+            writer.add_text(2, "field", "foo qux bar").unwrap();
+            writer.add_text(3, "field", "bar foo").unwrap();
+            writer.add_text(4, "field", "foo qux qux qux bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        for phrase_strategy in [PhraseStrategy::Joins, PhraseStrategy::Intersection] {
+            let conn = mem::replace(
+                index.connection_mut(),
+                Connection::open_in_memory().unwrap(),
+            );
+            index = Index::open(
+                conn,
+                Config {
+                    phrase_strategy,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let mut reader = index.read().unwrap();
+
+            let mut search = |text: &str| {
+                let query = reader.parse(text).unwrap();
+
+                reader.search(&*query, None, None, None).unwrap()
+            };
+
+            let ids = |results: &[(i64, f64)]| {
+                let mut ids = results.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+                ids.sort();
+                ids
+            };
+
+            assert_eq!(ids(&search("field:\"bar foo\"")), [3]);
+            assert_eq!(ids(&search("field:\"bar foo\"~1")), [1, 2, 3]);
+
+            // The terms can be up to three positions apart in either order.
+            let results = search("field:\"bar foo\"~2");
+            assert_eq!(ids(&results), [1, 2, 3]);
+            assert_eq!(results[0].0, 3);
+            assert_eq!(results[1].0, 1);
+            assert!((results[0].1 / results[1].1 - 3.0).abs() < 1e-9);
+
+            assert_eq!(ids(&search("field:\"bar foo\"~3")), [1, 2, 3, 4]);
+            assert_eq!(ids(&search("field:\"foo bar\"~3")), [1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn phrase_same_position() {
+        let mut synonyms = HashMap::new();
+        synonyms.insert("quick".to_owned(), vec!["fast".to_owned()]);
+
+        for phrase_strategy in [PhraseStrategy::Joins, PhraseStrategy::Intersection] {
+            let mut index = Index::in_memory(Config {
+                phrase_strategy,
+                ..Default::default()
+            })
+            .unwrap();
+
+            index.add_tokenizer(
+                "synonyms".to_owned(),
+                SplitNonAlphanumeric
+                    .chain(ToLowerCase::default())
+                    .chain(Synonyms::new(synonyms.clone()))
+                    .into(),
+            );
+            index.add_field("field", "synonyms").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", "quick fox").unwrap();
+                writer.add_text(2, "field", "fast fox").unwrap();
+                writer.add_text(3, "field", "quick red fox").unwrap();
+                writer.add_text(4, "field", "fox fast").unwrap();
+                writer.add_text(5, "field", "fast quick fox").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let mut search = |text: &str| {
+                let query = reader.parse(text).unwrap();
+
+                let mut ids = reader
+                    .search(&*query, None, None, None)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            };
+
+            // Either alternative suffices, but having both does not match twice.
+            assert_eq!(search("field:\"quick fox\""), [1, 2, 5]);
+            assert_eq!(search("field:\"fast fox\""), [1, 2, 5]);
+            assert_eq!(search("field:\"fox quick\""), [4]);
+
+            // An alternative at one position does not stand in for the next one.
+            assert_eq!(search("field:\"quick fast\""), [5]);
+            assert_eq!(search("field:\"quick fast fox\""), [5]);
+
+            assert_eq!(search("field:\"quick fo*\""), [1, 2, 5]);
+        }
+    }
+
+    #[test]
+    fn min_should_match() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let mut search = |must: &[&str], should: &[&str], must_not: &[&str], min: usize| {
+            let clauses = must
+                .iter()
+                .map(|text| (Occur::Must, *text))
+                .chain(should.iter().map(|text| (Occur::Should, *text)))
+                .chain(must_not.iter().map(|text| (Occur::MustNot, *text)))
+                .map(|(occur, text)| (occur, reader.parse(text).unwrap()))
+                .collect::<Vec<_>>();
+            let query = CombinedQuery::new(clauses).with_min_should_match(min);
+
+            let mut ids = reader
+                .search(&query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        let should = ["field:foo", "field:bar", "field:baz"];
+
+        assert_eq!(search(&[], &should, &[], 1), [1, 2, 3, 4]);
+        assert_eq!(search(&[], &should, &[], 2), [1]);
+        assert_eq!(search(&[], &should, &[], 3), [1]);
+        assert_eq!(search(&[], &should, &[], 4), []);
+        assert_eq!(search(&[], &should, &["field:baz"], 2), []);
+
+        assert_eq!(search(&["field:bar"], &should[..1], &[], 1), [1, 3]);
+        assert_eq!(search(&["field:bar"], &should[..1], &[], 2), []);
+        assert_eq!(
+            search(&["field:bar"], &["field:foo", "field:baz"], &[], 2),
+            [1]
+        );
+    }
+
+    #[test]
+    fn payloads() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "field".to_owned(),
+            FieldConfig {
+                sum_payloads: true,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer
+                .add_tokens_weighted(
+                    1,
+                    "field",
+                    &[("foo".to_owned(), 0, 0.5), ("bar".to_owned(), 1, 1.0)],
+                )
+                .unwrap();
+            writer
+                .add_tokens_weighted(
+                    2,
+                    "field",
+                    &[("foo".to_owned(), 0, 2.0), ("bar".to_owned(), 1, 1.0)],
+                )
+                .unwrap();
+            writer
+                .add_tokens(3, "field", &[("foo".to_owned(), 0), ("bar".to_owned(), 1)])
+                .unwrap();
+            writer
+                .add_tokens_weighted(
+                    4,
+                    "field",
+                    &[("foo".to_owned(), 0, 1.5), ("foo".to_owned(), 1, 1.5)],
+                )
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let (documents, avg_len) = reader
+            .query_row(
+                "SELECT documents, CAST(total AS REAL) / documents FROM canter_field_stats",
+                (),
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, f64>(1)?)),
+            )
+            .unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(id, _)| *id);
+
+        for ((id, score), payload) in results.iter().zip([0.5, 2.0, 1.0, 3.0]) {
+            assert_eq!(
+                *score,
+                bm25_weighted(&Config::default(), documents, avg_len, 5, payload, 2),
+                "{id}"
+            );
+        }
+
+        drop(reader);
+
+        // Without the option, payloads do not affect scores.
+        let conn = mem::replace(
+            index.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let index = Index::open(conn, Config::default()).unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(id, _)| *id);
+
+        assert_eq!(results[0].1, results[1].1);
+        assert_eq!(results[0].1, results[2].1);
+    }
+
+    #[test]
+    fn boost_and_constant_score() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:bar").unwrap();
+        let mut expected = reader.search(&*query, None, None, None).unwrap();
+        expected.sort_by_key(|(id, _)| *id);
+        assert_eq!(expected.len(), 2);
+
+        let query = BoostQuery::new(reader.parse("field:bar").unwrap(), 2.5);
+        let mut results = reader.search(&query, None, None, None).unwrap();
+        results.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            results,
+            expected
+                .iter()
+                .map(|(id, score)| (*id, 2.5 * score))
+                .collect::<Vec<_>>()
+        );
+
+        let query = ConstantScoreQuery::new(reader.parse("field:bar").unwrap(), 3.0);
+        let mut results = reader.search(&query, None, None, None).unwrap();
+        results.sort_by_key(|(id, _)| *id);
+        assert_eq!(results, [(1, 3.0), (3, 3.0)]);
+
+        let query = CombinedQuery::new([
+            (
+                Occur::Should,
+                BoostQuery::new(
+                    ConstantScoreQuery::new(reader.parse("field:foo").unwrap(), 1.0).into(),
+                    2.0,
+                )
+                .into(),
+            ),
+            (
+                Occur::Should,
+                ConstantScoreQuery::new(reader.parse("field:baz").unwrap(), 0.5).into(),
+            ),
+        ])
+        .with_coordination(Coordination::Disabled);
+        let mut results = reader.search(&query, None, None, None).unwrap();
+        results.sort_by_key(|(id, _)| *id);
+        assert_eq!(results, [(1, 2.5), (2, 2.0), (4, 0.5)]);
+    }
+
+    #[test]
+    fn score_histogram() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            for document_id in 0..20 {
+                let text = vec!["foo"; 1 + document_id as usize % 7].join(" ");
+
+                writer
+                    .add_text(document_id, "field", &format!("{text} bar"))
+                    .unwrap();
+            }
+
+            writer.add_text(20, "field", "bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        let (results, total) = reader.search_with_total(&*query, None, None).unwrap();
+        assert_eq!(total, 20);
+
+        let min = results
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::MAX, f64::min);
+
+        let histogram = reader.score_histogram(&*query, 4).unwrap();
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[0].0, min);
+        assert!(histogram.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(
+            histogram.iter().map(|(_, count)| count).sum::<usize>(),
+            total
+        );
+
+        let histogram = reader.score_histogram(&*query, 1).unwrap();
+        assert_eq!(histogram, [(min, total)]);
+
+        let histogram = reader.score_histogram(&*query, usize::MAX).unwrap();
+        assert_eq!(histogram.len(), Reader::MAX_BUCKETS);
+
+        let query = reader.parse("field:bar").unwrap();
+        let histogram = reader.score_histogram(&*query, 3).unwrap();
+        assert_eq!(histogram.iter().map(|(_, count)| count).sum::<usize>(), 21);
+
+        let query = reader.parse("field:missing").unwrap();
+        assert_eq!(reader.score_histogram(&*query, 3).unwrap(), []);
+    }
+
+    #[test]
+    fn parse_groups() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("+field:bar (field:foo field:baz)").unwrap();
+        let clauses = query.clauses();
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(clauses[0].0, Occur::Should);
+        assert_eq!(clauses[1].0, Occur::Must);
+
+        let group = clauses[0].1.clauses();
+        assert_eq!(group.len(), 2);
+        assert!(group.iter().all(|(occur, _)| *occur == Occur::Should));
+        assert!(clauses[1].1.clauses().is_empty());
+
+        let mut search = |text: &str| {
+            let query = reader.parse(text).unwrap();
+
+            let mut ids = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("+field:bar (field:foo field:baz)"), [1, 3]);
+        assert_eq!(search("+field:bar +(field:foo field:baz)"), [1]);
+        assert_eq!(search("field:foo -(field:bar field:baz)"), [2]);
+        assert_eq!(search("-(field:foo field:bar)"), [4]);
+        assert_eq!(search("( (field:foo) +(field:bar -(field:baz)))"), [3]);
+        assert_eq!(search("field:foo\\)"), [1, 2]);
+
+        for text in [
+            "(field:foo",
+            "field:foo)",
+            "((field:foo) field:bar",
+            "field:foo ) field:bar",
+        ] {
+            let err = reader.parse(text).err().unwrap();
+            assert!(matches!(err, Error::UnbalancedParentheses(_)), "{text}");
+        }
+    }
+
+    #[test]
+    fn attached_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("ATTACH DATABASE ':memory:' AS \"my index\"", ())
+            .unwrap();
+
+        let config = Config {
+            schema: "my index".to_owned(),
+            ..Default::default()
+        };
+
+        let mut index = Index::open(conn, config.clone()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo bar").unwrap();
+            writer.add_text(2, "field", "bar").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:bar").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 2);
+
+            let main = reader
+                .query_row(
+                    "SELECT COUNT(*) FROM main.sqlite_schema WHERE name LIKE 'canter%'",
+                    (),
+                    |row| row.get::<_, usize>(0),
+                )
+                .unwrap();
+            assert_eq!(main, 0);
+
+            let postings = reader
+                .query_row(
+                    "SELECT COUNT(*) FROM \"my index\".canter_postings",
+                    (),
+                    |row| row.get::<_, usize>(0),
+                )
+                .unwrap();
+            assert_eq!(postings, 3);
+        }
+
+        let stats = index.stats().unwrap();
+        assert_eq!(stats.postings, 3);
+        assert_ne!(stats.bytes, 0);
+
+        // Another index in `main` of the same connection is kept separate.
+        let conn = mem::replace(
+            index.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let mut other = Index::open(conn, Default::default()).unwrap();
+
+        other.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = other.rewrite().unwrap();
+
+            writer.add_text(3, "field", "bar baz").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let conn = mem::replace(
+            other.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let mut index = Index::open(conn, config).unwrap();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.delete_document(2).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:bar").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].0, 1);
+
+            let query = reader.parse("field:baz").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 0);
+        }
+
+        assert_eq!(index.stats().unwrap().postings, 2);
+
+        let conn = mem::replace(
+            index.connection_mut(),
+            Connection::open_in_memory().unwrap(),
+        );
+        let other = Index::open(conn, Default::default()).unwrap();
+
+        let mut reader = other.read().unwrap();
+
+        let query = reader.parse("field:bar").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 3);
+    }
+
+    #[test]
+    fn parse_operators() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo AND NOT field:bar").unwrap();
+        let clauses = query.clauses();
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(clauses[0].0, Occur::Must);
+        assert_eq!(clauses[1].0, Occur::MustNot);
+
+        let mut search = |text: &str| {
+            let query = reader.parse(text).unwrap();
+
+            let mut ids = reader
+                .search(&*query, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("field:foo AND field:bar"), [1]);
+        assert_eq!(search("field:foo OR field:bar"), [1, 2, 3]);
+        assert_eq!(search("NOT field:foo"), [3, 4]);
+        assert_eq!(search("field:foo AND NOT field:bar"), [2]);
+        assert_eq!(search("field:baz OR field:foo AND field:bar"), [1, 4]);
+        assert_eq!(search("field:bar AND (field:foo OR field:baz)"), [1]);
+        assert_eq!(search("+field:foo field:bar AND field:baz"), [1, 2]);
+        assert_eq!(search("field:AND"), []);
+
+        // Lowercase keywords are not operators, but the start of the next clause.
+        let err = reader.parse("field:foo and field:bar").err().unwrap();
+        assert!(matches!(err, Error::NoSuchField(name) if name == "and field"));
+
+        for text in [
+            "field:foo AND",
+            "field:foo OR",
+            "NOT",
+            "AND field:foo",
+            "field:foo AND OR field:bar",
+            "NOT NOT field:foo",
+            "(field:foo AND) field:bar",
+        ] {
+            let err = reader.parse(text).err().unwrap();
+            assert!(matches!(err, Error::DanglingOperator(_)), "{text}");
+        }
+    }
+
+    #[test]
+    fn index_stats() {
+        let index = Index::in_memory(Default::default()).unwrap();
+
+        let stats = index.stats().unwrap();
+        assert_eq!(stats.fields, 0);
+        assert_eq!(stats.postings, 0);
+        assert_ne!(stats.bytes, 0);
+
+        let index = sample_index();
+
+        let stats = index.stats().unwrap();
+        assert_eq!(stats.fields, 1);
+        assert_eq!(stats.terms, 3);
+        assert_eq!(stats.postings, 6);
+        assert_eq!(stats.documents, 4);
+        assert_ne!(stats.bytes, 0);
+    }
+
+    #[test]
+    fn count() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        for text in [
+            "field:foo",
+            "field:foo field:bar field:baz",
+            "+field:foo -field:bar",
+            "field:\"foo bar\"",
+            "field:ba*",
+            "field:missing",
+        ] {
+            let query = reader.parse(text).unwrap();
+
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(reader.count(&*query).unwrap(), results.len(), "{text}");
+        }
+
+        reader.with_document_range(2, 3);
+
+        let query = reader.parse("field:foo field:bar").unwrap();
+        assert_eq!(reader.count(&*query).unwrap(), 2);
+    }
+
+    #[test]
+    fn preserve_term_ids() {
+        let term_ids = |index: &Index| {
+            index
+                .connection()
+                .prepare("SELECT value, id, count FROM canter_terms ORDER BY value")
+                .unwrap()
+                .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<Result<Vec<(String, i64, usize)>, _>>()
+                .unwrap()
+        };
+
+        for preserve_term_ids in [false, true] {
+            let mut index = Index::in_memory(Config {
+                preserve_term_ids,
+                ..Default::default()
+            })
+            .unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", "foo bar").unwrap();
+                writer.add_text(2, "field", "baz").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let before = term_ids(&index);
+
+            for _ in 0..2 {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", "qux baz").unwrap();
+                writer.add_text(2, "field", "bar bar").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let after = term_ids(&index);
+
+            let values = after
+                .iter()
+                .map(|(value, _id, count)| (value.as_str(), *count))
+                .collect::<Vec<_>>();
+            assert_eq!(values, [("bar", 2), ("baz", 1), ("qux", 1)]);
+
+            let preserved = ["bar", "baz"].iter().all(|value| {
+                let id = |terms: &[(String, i64, usize)]| {
+                    terms.iter().find(|(other, _, _)| other == value).unwrap().1
+                };
+
+                id(&before) == id(&after)
+            });
+            assert_eq!(preserved, preserve_term_ids);
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:bar").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].0, 2);
+        }
+    }
+
+    #[test]
+    fn phrase_spans() {
+        let mut index = sample_index();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_text(5, "field", "bar baz foo bar baz").unwrap();
+            writer.add_values(6, "field", &["bar", "baz"]).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(reader.positions("field", "bar", 1).unwrap(), [2]);
+        assert_eq!(reader.positions("field", "baz", 1).unwrap(), [3]);
+
+        assert_eq!(
+            reader.phrase_spans("field", "bar baz").unwrap(),
+            [(1, vec![(2, 3)]), (5, vec![(1, 2), (4, 5)])]
+        );
+        assert_eq!(
+            reader.phrase_spans("field", "FOO bar baz").unwrap(),
+            [(1, vec![(1, 3)]), (5, vec![(3, 5)])]
+        );
+        assert_eq!(
+            reader.phrase_spans("field", "baz").unwrap(),
+            [
+                (1, vec![(3, 3)]),
+                (4, vec![(1, 1)]),
+                (5, vec![(2, 2), (5, 5)]),
+                (6, vec![(2, 2)])
+            ]
+        );
+        assert_eq!(reader.phrase_spans("field", "baz bar").unwrap(), []);
+        assert_eq!(reader.phrase_spans("field", "").unwrap(), []);
+    }
+
+    #[test]
+    fn stored_text() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "stored".to_owned(),
+            FieldConfig {
+                store: true,
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        index.add_field("stored", "default").unwrap();
+        index.add_field("plain", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "stored", "Foo Bar").unwrap();
+            writer.add_text(1, "stored", "Baz").unwrap();
+            writer.add_text(1, "plain", "Qux").unwrap();
+            writer.add_stored_text(2, "plain", "Quux").unwrap();
+            writer.add_stored_text(2, "stored", "Corge").unwrap();
+            writer
+                .add_values(4, "stored", &["Garply", "Waldo"])
+                .unwrap();
+            writer
+                .add_tokens(5, "stored", &[("fred".to_owned(), 0)])
+                .unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        {
+            let mut reader = index.read().unwrap();
+
+            assert_eq!(
+                reader.stored_text(1, "stored").unwrap().as_deref(),
+                Some("Foo Bar\nBaz")
+            );
+            assert_eq!(reader.stored_text(1, "plain").unwrap(), None);
+            assert_eq!(
+                reader.stored_text(2, "plain").unwrap().as_deref(),
+                Some("Quux")
+            );
+            assert_eq!(
+                reader.stored_text(2, "stored").unwrap().as_deref(),
+                Some("Corge")
+            );
+            assert_eq!(reader.stored_text(3, "stored").unwrap(), None);
+            assert_eq!(
+                reader.stored_text(4, "stored").unwrap().as_deref(),
+                Some("Garply\nWaldo")
+            );
+            assert_eq!(reader.stored_text(5, "stored").unwrap(), None);
+
+            reader.with_allowed_fields(&["plain"]);
+
+            let err = reader.stored_text(1, "stored").err().unwrap();
+            assert!(matches!(err, Error::FieldNotAllowed(field) if field == "stored"));
+        }
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_text(1, "stored", "Grault").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        index.reindex().unwrap();
+
+        let mut reader = index.read().unwrap();
+
+        assert_eq!(
+            reader.stored_text(1, "stored").unwrap().as_deref(),
+            Some("Grault")
+        );
+        assert_eq!(
+            reader.stored_text(2, "stored").unwrap().as_deref(),
+            Some("Corge")
+        );
+
+        let query = reader.parse("stored:grault").unwrap();
+        assert_eq!(reader.search(&*query, None, None, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn posting_buffer() {
+        let mut all_contents = Vec::new();
+
+        for posting_buffer in [None, Some(3), Some(1000)] {
+            let config = Config {
+                posting_buffer,
+                ..Default::default()
+            };
+
+            let mut index = Index::in_memory(config).unwrap();
+            index.add_field("field", "default").unwrap();
+            index.add_field("other", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", "foo bar baz").unwrap();
+                writer.add_text(2, "field", "bar qux").unwrap();
+                writer.add_text(2, "other", "foo").unwrap();
+                writer
+                    .add_tokens(
+                        3,
+                        "field",
+                        &[("foo".to_owned(), 0), ("bar".to_owned(), MAX_POSITION)],
+                    )
+                    .unwrap_err();
+                writer.add_text(3, "field", "baz foo").unwrap();
+                writer.delete_document(2).unwrap();
+                writer.add_text(4, "field", "qux qux").unwrap();
+                writer.add_text(1, "field", "quux").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            {
+                let mut writer = index.update().unwrap();
+
+                writer.add_text(1, "field", "bar").unwrap();
+                writer.add_text(5, "other", "bar baz").unwrap();
+                writer.flush().unwrap();
+                writer.delete_field("other").unwrap();
+                writer.add_text(6, "field", "foo").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let reader = index.read().unwrap();
+
+            let postings = reader
+                .prepare("SELECT term_id, document_id, position, segment, payload FROM canter_postings ORDER BY 1, 2, 3")
+                .unwrap()
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, usize>(2)?,
+                        row.get::<_, usize>(3)?,
+                        row.get::<_, f64>(4)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            let terms = reader
+                .prepare("SELECT id, field_id, value, count FROM canter_terms ORDER BY id")
+                .unwrap()
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, usize>(3)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            let documents = reader
+                .prepare("SELECT field_id, document_id, count FROM canter_documents ORDER BY 1, 2")
+                .unwrap()
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, usize>(2)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert!(!postings.is_empty());
+
+            all_contents.push((postings, terms, documents));
+        }
+
+        assert_eq!(all_contents[0], all_contents[1]);
+        assert_eq!(all_contents[0], all_contents[2]);
+    }
+
+    #[test]
+    fn nested_must_not() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let foo_without_bar = CombinedQuery::new([
+            (Occur::Must, reader.parse("field:foo").unwrap()),
+            (Occur::MustNot, reader.parse("field:bar").unwrap()),
+        ]);
+        let bar_without_foo = CombinedQuery::new([
+            (Occur::Must, reader.parse("field:bar").unwrap()),
+            (Occur::MustNot, reader.parse("field:foo").unwrap()),
+        ]);
+
+        let query = CombinedQuery::new([
+            (Occur::Should, foo_without_bar.into()),
+            (Occur::Should, reader.parse("field:bar").unwrap()),
+            (Occur::MustNot, bar_without_foo.into()),
+        ]);
+        let mut results = reader.search(&query, None, None, None).unwrap();
+        results.sort_by_key(|(document_id, _score)| *document_id);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 2);
+
+        let query = reader.parse("field:baz (field:foo -field:bar)").unwrap();
+        let mut results = reader.search(&*query, None, None, None).unwrap();
+        results.sort_by_key(|(document_id, _score)| *document_id);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[1].0, 2);
+        assert_eq!(results[2].0, 4);
+        assert_eq!(results[1].1, results[2].1);
+    }
+
+    #[test]
+    fn explain() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar -field:qux").unwrap();
+
+        let results = reader.search(&*query, None, None, None).unwrap();
+        let score = results
+            .iter()
+            .find(|(document_id, _score)| *document_id == 1)
+            .unwrap()
+            .1;
+
+        let explanation = reader.explain(&*query, 1).unwrap();
+        let lines = explanation.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], format!("query: {score}"));
+        assert!(lines[1].starts_with("  should[0]: "));
+        assert!(lines[2].starts_with("    field:foo: idf "));
+        assert!(lines[2].contains("tf 1, doc_len 3, avg_len 1.5, boost 1"));
+        assert!(lines[3].starts_with("  should[1]: "));
+        assert!(lines[4].starts_with("    field:bar: idf "));
+        assert_eq!(lines[5], "  must_not[0]: no match");
+
+        let foo_score = bm25(&Default::default(), 4, 1.5, 2, 1, 3);
+        assert_eq!(lines[1], format!("  should[0]: {foo_score}"));
+
+        let explanation = reader.explain(&*query, 4).unwrap();
+        assert!(explanation.starts_with("query: no match\n"));
+    }
+
+    #[test]
+    fn field_bm25() {
+        let mut config = Config::default();
+        config.fields.insert(
+            "title".to_owned(),
+            FieldConfig {
+                bm25_b: Some(0.0),
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config).unwrap();
+        index.add_field("title", "default").unwrap();
+        index.add_field("body", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            for field_name in ["title", "body"] {
+                writer.add_text(1, field_name, "rust").unwrap();
+                writer
+                    .add_text(2, field_name, "rust programming language guide")
+                    .unwrap();
+                writer.add_text(3, field_name, "python").unwrap();
+            }
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("title:rust").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].1, results[1].1);
+
+        let config = Config {
+            bm25_b: 0.0,
+            ..Default::default()
+        };
+        assert!((results[0].1 - bm25(&config, 3, 2.0, 2, 1, 4)).abs() < 1e-9);
+
+        let query = reader.parse("body:rust").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 1);
+        assert!(results[0].1 > results[1].1);
+
+        let query = reader.parse("title:\"rust programming\"").unwrap();
+        let results = reader.search(&*query, None, None, None).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn keep_dotted_numbers() {
+        let mut tokens = Vec::new();
+        SplitKeepingDottedNumbers
+            .tokenize(
+                "Release v2.0.1, see 1.2. Next: a.b 3.x",
+                |token, position| {
+                    tokens.push((token.to_owned(), position));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            tokens,
+            [
+                ("Release".to_owned(), 0),
+                ("v2.0.1".to_owned(), 1),
+                ("see".to_owned(), 2),
+                ("1.2".to_owned(), 3),
+                ("Next".to_owned(), 4),
+                ("a".to_owned(), 5),
+                ("b".to_owned(), 6),
+                ("3".to_owned(), 7),
+                ("x".to_owned(), 8),
+            ]
+        );
+
+        for keep_dotted_numbers in [false, true] {
+            let config = Config {
+                keep_dotted_numbers,
+                ..Default::default()
+            };
+
+            let mut index = Index::in_memory(config).unwrap();
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer
+                    .add_text(1, "field", "Released V2.0.1 today")
+                    .unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:v2.0.1").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 1);
+
+            let query = reader.parse("field:v2").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), usize::from(!keep_dotted_numbers));
+
+            let positions = reader.positions("field", "v2.0.1", 1).unwrap();
+            assert_eq!(!positions.is_empty(), keep_dotted_numbers);
+        }
+    }
+
+    #[test]
+    fn set_field_boost() {
+        let mut rankings = Vec::new();
+
+        for body_boost in [None, Some(5.0)] {
+            let mut config = Config::default();
+
+            if let Some(body_boost) = body_boost {
+                config.set_field_boost("body", body_boost);
+            }
+
+            assert_eq!(
+                config.fields.get("body").map(|config| config.boost),
+                body_boost
+            );
+
+            let mut index = Index::in_memory(config).unwrap();
+            index.add_field("title", "default").unwrap();
+            index.add_field("body", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "title", "other").unwrap();
+                writer.add_text(1, "body", "rust rust").unwrap();
+                writer.add_text(2, "title", "rust").unwrap();
+                writer.add_text(2, "body", "other").unwrap();
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("title:rust body:rust").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+            assert_eq!(results.len(), 2);
+
+            rankings.push(
+                results
+                    .iter()
+                    .map(|(document_id, _score)| *document_id)
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(rankings, [[2, 1], [1, 2]]);
+    }
+
+    #[test]
+    fn commit_reader() {
+        let index = sample_index();
+
+        for commit in [false, true] {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            reader.search(&*query, None, None, Some("stage_1")).unwrap();
+
+            let query = TempTableQuery::new("stage_1".to_owned());
+            let results = reader.search(&query, None, None, None).unwrap();
+            assert_eq!(results.len(), 2);
+
+            if commit {
+                reader.commit().unwrap();
+            } else {
+                drop(reader);
+            }
+
+            let reader = index.read().unwrap();
+
+            let exists = reader
+                .query_row(
+                    "SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'stage_1'",
+                    (),
+                    |row| row.get::<_, bool>(0),
+                )
+                .unwrap();
+            assert_eq!(exists, commit);
+
+            if commit {
+                let query = TempTableQuery::new("stage_1".to_owned());
+                let results = reader.search(&query, None, None, None).unwrap();
+                assert_eq!(results.len(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn search_sorted() {
+        let mut index = sample_index();
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_sort_key(1, 30).unwrap();
+            writer.add_sort_key(2, 10).unwrap();
+            writer.add_sort_key(3, 10).unwrap();
+
+            writer.add_timestamp(4, 100).unwrap();
+            writer.add_timestamp(1, 200).unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo field:bar field:baz").unwrap();
+
+        let document_ids = |key, order, limit, offset| {
+            reader
+                .search_sorted(&*query, key, order, limit, offset)
+                .unwrap()
+                .into_iter()
+                .map(|(document_id, _score)| document_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            document_ids(SortKey::Key, Order::Ascending, None, None),
+            [2, 3, 1, 4]
+        );
+        assert_eq!(
+            document_ids(SortKey::Key, Order::Descending, None, None),
+            [1, 3, 2, 4]
+        );
+        assert_eq!(
+            document_ids(SortKey::Key, Order::Descending, Some(2), Some(1)),
+            [3, 2]
+        );
+        assert_eq!(
+            document_ids(SortKey::Timestamp, Order::Descending, None, None),
+            [1, 4, 3, 2]
+        );
+
+        let results = reader
+            .search_sorted(&*query, SortKey::Key, Order::Ascending, Some(1), None)
+            .unwrap();
+        let scores = reader.search(&*query, None, None, None).unwrap();
+        assert!(scores.contains(&results[0]));
+    }
+
+    #[test]
+    fn stored_scoring() {
+        let config = Config {
+            bm25_k1: 1.2,
+            bm25_b: 0.5,
+            ..Default::default()
+        };
+
+        let mut index = Index::in_memory(config).unwrap();
+
+        let reopen = |index: &mut Index, config: Config| {
+            let conn = mem::replace(
+                index.connection_mut(),
+                Connection::open_in_memory().unwrap(),
+            );
+            Index::open(conn, config)
+        };
+
+        let err = reopen(&mut index, Default::default()).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::ScoringConflict { name, value, stored } if name == "bm25_k1" && value == Some(2.0) && stored == Some(1.2)
+        ));
+
+        let config = Config {
+            stored_scoring: StoredScoring::Adopt,
+            ..Default::default()
+        };
+
+        let mut index = Index::in_memory(Config {
+            bm25_k1: 1.2,
+            bm25_b: 0.5,
+            ..Default::default()
+        })
+        .unwrap();
+
+        index = reopen(&mut index, config).unwrap();
+        assert_eq!(index.config().bm25_k1, 1.2);
+        assert_eq!(index.config().bm25_b, 0.5);
+
+        let reader = index.read().unwrap();
+        let score = reader
+            .query_row("SELECT canter_bm25(4, 1.0, 2, 1, 2)", (), |row| {
+                row.get::<_, f64>(0)
+            })
+            .unwrap();
+        assert_eq!(score, bm25(index.config(), 4, 1.0, 2, 1, 2));
+        drop(reader);
+
+        let config = Config {
+            stored_scoring: StoredScoring::Overwrite,
+            ..Default::default()
+        };
+
+        index = reopen(&mut index, config).unwrap();
+        assert_eq!(index.config().bm25_k1, 2.0);
+
+        index = reopen(&mut index, Default::default()).unwrap();
+        assert_eq!(index.config().bm25_b, 0.75);
+
+        let mut config = Config {
+            max_term_frequency: Some(3),
+            ..Default::default()
+        };
+        config.fields.insert(
+            "title".to_owned(),
+            FieldConfig {
+                bm25_b: Some(0.0),
+                ..Default::default()
+            },
+        );
+
+        let mut index = Index::in_memory(config.clone()).unwrap();
+
+        let err = reopen(&mut index, Default::default()).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::ScoringConflict { name, value, stored } if name == "fields.title.bm25_b" && value.is_none() && stored == Some(0.0)
+        ));
+
+        let mut other = Config::default();
+        other.fields.insert(
+            "title".to_owned(),
+            FieldConfig {
+                bm25_k1: Some(1.0),
+                ..Default::default()
+            },
+        );
+        other.stored_scoring = StoredScoring::Adopt;
+
+        let mut index = Index::in_memory(config.clone()).unwrap();
+
+        index = reopen(&mut index, other).unwrap();
+        assert_eq!(index.config().max_term_frequency, Some(3));
+        assert_eq!(index.config().fields["title"].bm25_k1, None);
+        assert_eq!(index.config().fields["title"].bm25_b, Some(0.0));
+
+        reopen(&mut index, config).unwrap();
+    }
+
+    #[test]
+    fn search_after() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = CombinedQuery::new([
+            (Occur::Should, reader.parse("field:foo").unwrap()),
+            (Occur::Should, reader.parse("field:bar").unwrap()),
+            (Occur::Should, reader.parse("field:baz").unwrap()),
+        ])
+        .with_coordination(Coordination::Disabled);
+
+        let mut expected = reader.search(&query, None, None, None).unwrap();
+        expected.sort_by(|lhs, rhs| rhs.1.total_cmp(&lhs.1).then(rhs.0.cmp(&lhs.0)));
+        assert_eq!(expected.len(), 4);
+
+        let mut pages = Vec::new();
+        let mut after = None;
+
+        loop {
+            let (results, cursor) = reader.search_after(&query, after, 3).unwrap();
+            pages.push(results);
+
+            match cursor {
+                Some(cursor) => after = Some(cursor),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].len(), 3);
+        assert_eq!(pages.concat(), expected);
+
+        let (results, cursor) = reader.search_after(&query, after, 1).unwrap();
+        assert_eq!(results, [expected[3]]);
+        assert_eq!(cursor, Some((expected[3].1, expected[3].0)));
+
+        let (results, cursor) = reader.search_after(&query, cursor, 1).unwrap();
+        assert_eq!(results, []);
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn quoted_temp_table() {
+        let index = sample_index();
+
+        let mut reader = index.read().unwrap();
+
+        let query = reader.parse("field:foo").unwrap();
+        reader
+            .search(&*query, None, None, Some("stage \"1\""))
+            .unwrap();
+
+        let query = TempTableQuery::new("stage \"1\"".to_owned());
+        let results = reader.search(&query, None, None, None).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn phrase_slop_scores_each_match() {
+        let mut scores = Vec::new();
+
+        for phrase_strategy in [PhraseStrategy::Joins, PhraseStrategy::Intersection] {
+            let mut index = Index::in_memory(Config {
+                phrase_strategy,
+                ..Default::default()
+            })
+            .unwrap();
+
+            index.add_field("field", "default").unwrap();
+
+            {
+                let mut writer = index.rewrite().unwrap();
+
+                writer.add_text(1, "field", "foo barx qux qux bar").unwrap();
+
+                for document_id in 2..6 {
+                    writer.add_text(document_id, "field", "barx").unwrap();
+                }
+
+                writer.commit().unwrap();
+            }
+
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:\"foo bar*\"~3").unwrap();
+            let results = reader.search(&*query, None, None, None).unwrap();
+
+            assert_eq!(results.len(), 1);
+            scores.push(results[0].1);
+        }
+
+        assert!((scores[0] - scores[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        let mut index = Index::in_memory(Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        let mut writer = index.rewrite().unwrap();
+
+        for payload in [f64::NAN, f64::INFINITY, -1.0] {
+            let err = writer
+                .add_tokens_weighted(
+                    1,
+                    "field",
+                    &[("foo".to_owned(), 0, 1.0), ("bar".to_owned(), 1, payload)],
+                )
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidPayload { token, .. } if token == "bar"));
+        }
+
+        writer
+            .add_tokens_weighted(1, "field", &[("foo".to_owned(), 0, 0.0)])
+            .unwrap();
+
+        writer.commit().unwrap();
+    }
+
+    #[test]
+    fn temp_tables_keep_field_cache() {
+        let index = sample_index();
+
+        {
+            let mut reader = index.read().unwrap();
+
+            let query = reader.parse("field:foo").unwrap();
+            reader.search(&*query, None, None, Some("stage")).unwrap();
+
+            reader
+                .execute(
+                    "INSERT INTO temp.stage (document_id, score) VALUES (5, 0.0)",
+                    (),
+                )
+                .unwrap();
+
+            reader.commit().unwrap();
+        }
+
+        drop(index.read().unwrap());
+
+        assert!(lock(&index.fields).contains_key("field"));
+    }
+
+    #[test]
+    fn read_connections() {
+        let open = || {
+            Connection::open_with_flags(
+                "file:/canter-read-connections?vfs=memdb",
+                OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+            )
+            .unwrap()
+        };
+
+        let mut index = Index::open(open(), Default::default()).unwrap();
+
+        index.add_field("field", "default").unwrap();
+
+        {
+            let mut writer = index.rewrite().unwrap();
+
+            writer.add_text(1, "field", "foo bar").unwrap();
+            writer.add_text(2, "field", "foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        index.add_read_connection(open()).unwrap();
+
+        let mut first = index.read().unwrap();
+        let mut second = index.try_read().unwrap();
+
+        // Waiting for a connection held by this thread would never end.
+        assert!(matches!(index.try_read(), Err(Error::Busy)));
+
+        let query = first.parse("field:foo").unwrap();
+        let first_results = first.search(&*query, None, None, None).unwrap();
+
+        let query = second.parse("field:foo").unwrap();
+        let second_results = second.search(&*query, None, None, None).unwrap();
+
+        assert_eq!(first_results.len(), 2);
+        assert_eq!(first_results, second_results);
+
+        drop(first);
+        drop(second);
+
+        {
+            let mut writer = index.update().unwrap();
+
+            writer.add_text(3, "field", "foo").unwrap();
+
+            writer.commit().unwrap();
+        }
+
+        // Both connections observe the change, including the updated field statistics.
+        let mut first = index.read().unwrap();
+        let mut second = index.read().unwrap();
+
+        let query = first.parse("field:foo").unwrap();
+        let first_results = first.search(&*query, None, None, None).unwrap();
+
+        let query = second.parse("field:foo").unwrap();
+        let second_results = second.search(&*query, None, None, None).unwrap();
+
+        assert_eq!(first_results.len(), 3);
+        assert_eq!(first_results, second_results);
+    }
 }
@@ -1,67 +1,1197 @@
 use std::fmt::Write;
+use std::mem::swap;
 
 use rusqlite::ToSql;
+use smallvec::SmallVec;
 
-use crate::Field;
+use crate::{Field, quote_identifier};
 
 pub trait Query {
     fn to_sql<'query>(
         &'query self,
+        schema: &str,
         score: bool,
         sql: &mut String,
         params: &mut Vec<&'query dyn ToSql>,
     );
+
+    /// Renders the documents which would match except for being excluded by `must_not` clauses,
+    /// returning `false` if the query cannot exclude any documents.
+    fn excluded_to_sql<'query>(
+        &'query self,
+        _schema: &str,
+        _sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        false
+    }
+
+    /// Renders the inputs to the BM25 score of each matched term as
+    /// `(document_id, field, term, boost, documents, avg_len, df, tf, doc_len)`,
+    /// returning `false` if the query does not score individual terms.
+    fn term_stats_to_sql<'query>(
+        &'query self,
+        _schema: &str,
+        _sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        false
+    }
+
+    /// Lists the top-level clauses of a combination, empty for all other queries.
+    fn clauses(&self) -> Vec<(Occur, &dyn Query)> {
+        Vec::new()
+    }
+
+    /// Identifies the field matched by a query scoped to a single field.
+    fn field_id(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl<Q> From<Q> for Box<dyn Query>
+where
+    Q: Query + 'static,
+{
+    fn from(query: Q) -> Self {
+        Box::new(query)
+    }
+}
+
+pub struct AllQuery;
+
+impl Query for AllQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if score {
+            write!(
+                sql,
+                "SELECT DISTINCT document_id, 1 AS score, 1 as terms FROM {schema}.canter_postings"
+            )
+        } else {
+            write!(
+                sql,
+                "SELECT DISTINCT document_id FROM {schema}.canter_postings"
+            )
+        }
+        .unwrap();
+    }
+}
+
+/// Matches all documents with any text added to a field, using a constant score.
+///
+/// Unlike [`AllQuery`], this does not need to scan all postings.
+pub struct ExistsQuery {
+    field_id: i64,
+}
+
+impl ExistsQuery {
+    pub(crate) fn new(field: &Field) -> Self {
+        Self { field_id: field.id }
+    }
+}
+
+impl Query for ExistsQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if score {
+            write!(
+                sql,
+                "SELECT document_id, 1 AS score, 1 AS terms FROM {schema}.canter_documents WHERE field_id = ?"
+            )
+        } else {
+            write!(
+                sql,
+                "SELECT document_id FROM {schema}.canter_documents WHERE field_id = ?"
+            )
+        }
+        .unwrap();
+
+        params.push(&self.field_id);
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.field_id)
+    }
+}
+
+pub struct MatchNoneQuery;
+
+impl Query for MatchNoneQuery {
+    fn to_sql<'query>(
+        &'query self,
+        _schema: &str,
+        score: bool,
+        sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        sql.push_str(if score {
+            "SELECT NULL AS document_id, 0 AS score, 0 AS terms WHERE 0"
+        } else {
+            "SELECT NULL AS document_id WHERE 0"
+        });
+    }
+}
+
+/// Matches the documents stored in a temporary table, e.g. created by passing `temp`
+/// to [`Reader::search`][crate::reader::Reader::search], without contributing to their score.
+pub struct TempTableQuery {
+    table: String,
+}
+
+impl TempTableQuery {
+    pub fn new(table: String) -> Self {
+        Self { table }
+    }
+}
+
+impl Query for TempTableQuery {
+    fn to_sql<'query>(
+        &'query self,
+        _schema: &str,
+        score: bool,
+        sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        let table = quote_identifier(&self.table);
+
+        if score {
+            write!(
+                sql,
+                "SELECT DISTINCT document_id, 0 AS score, 0 AS terms FROM temp.{table}"
+            )
+            .unwrap();
+        } else {
+            write!(sql, "SELECT DISTINCT document_id FROM temp.{table}").unwrap();
+        }
+    }
+}
+
+/// Multiplies the scores of an inner query by a constant factor.
+pub struct BoostQuery {
+    inner: Box<dyn Query>,
+    boost: f64,
+}
+
+impl BoostQuery {
+    pub fn new(inner: Box<dyn Query>, boost: f64) -> Self {
+        Self { inner, boost }
+    }
+}
+
+impl Query for BoostQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if !score {
+            return self.inner.to_sql(schema, false, sql, params);
+        }
+
+        // Debug formatting ensures a floating point literal, avoiding integer arithmetic.
+        write!(
+            sql,
+            "SELECT document_id, {:?} * score AS score, terms FROM (",
+            self.boost
+        )
+        .unwrap();
+
+        self.inner.to_sql(schema, true, sql, params);
+
+        sql.push(')');
+    }
+
+    fn excluded_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        self.inner.excluded_to_sql(schema, sql, params)
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        self.inner.field_id()
+    }
+}
+
+/// Matches the same documents as an inner query, but replaces their scores by a constant.
+pub struct ConstantScoreQuery {
+    inner: Box<dyn Query>,
+    score: f64,
+}
+
+impl ConstantScoreQuery {
+    pub fn new(inner: Box<dyn Query>, score: f64) -> Self {
+        Self { inner, score }
+    }
+}
+
+impl Query for ConstantScoreQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if !score {
+            return self.inner.to_sql(schema, false, sql, params);
+        }
+
+        write!(
+            sql,
+            "SELECT document_id, {:?} AS score, 1 AS terms FROM (",
+            self.score
+        )
+        .unwrap();
+
+        // The inner scores are discarded, so there is no need to compute them.
+        self.inner.to_sql(schema, false, sql, params);
+
+        sql.push(')');
+    }
+
+    fn excluded_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        self.inner.excluded_to_sql(schema, sql, params)
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        self.inner.field_id()
+    }
+}
+
+pub struct TermQuery {
+    field_id: i64,
+    documents: usize,
+    avg_documents_count: f64,
+    boost: f64,
+    proximity_boost: f64,
+    value: String,
+    sum_payloads: bool,
+    bm25: Option<(f64, f64)>,
+}
+
+impl TermQuery {
+    pub(crate) fn new(field: &Field, boost: f64, proximity_boost: f64, value: String) -> Self {
+        Self {
+            field_id: field.id,
+            documents: field.documents,
+            avg_documents_count: field.avg_documents_count,
+            boost,
+            proximity_boost,
+            value,
+            sum_payloads: false,
+            bm25: None,
+        }
+    }
+
+    /// Uses the sum of the payloads of the term's postings within a document
+    /// instead of their number as the term frequency.
+    pub(crate) fn with_sum_payloads(mut self, sum_payloads: bool) -> Self {
+        self.sum_payloads = sum_payloads;
+        self
+    }
+
+    /// Overrides `k1` and `b` of the BM25 score, cf. [`FieldConfig::bm25_k1`][crate::FieldConfig::bm25_k1].
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.bm25 = bm25;
+        self
+    }
+}
+
+impl Query for TermQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if score {
+            write!(
+                sql,
+                "SELECT canter_postings.document_id AS document_id,\n{} * ",
+                self.boost,
+            )
+            .unwrap();
+
+            bm25_to_sql(
+                self.documents,
+                self.avg_documents_count,
+                self.term_frequency(),
+                self.bm25,
+                sql,
+            );
+
+            proximity_to_sql(self.proximity_boost, "canter_postings.position", sql);
+
+            sql.push_str(" AS score,\n1 as terms");
+        } else {
+            sql.push_str("SELECT canter_postings.document_id AS document_id");
+        }
+
+        write!(
+            sql,
+            " FROM {schema}.canter_terms\nJOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id",
+        )
+        .unwrap();
+
+        if score {
+            write!(sql, "\nJOIN {schema}.canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id").unwrap();
+        }
+
+        sql.push_str("\nWHERE canter_terms.field_id = ? AND canter_terms.value = ? GROUP BY canter_postings.term_id, canter_postings.document_id");
+
+        params.push(&self.field_id);
+        params.push(&self.value);
+    }
+
+    fn term_stats_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        write!(
+            sql,
+            r#"SELECT canter_postings.document_id AS document_id, canter_fields.name, canter_terms.value,
+               {:?}, {}, {:?}, canter_terms.count, {}, canter_documents.count
+               FROM {schema}.canter_terms
+               JOIN {schema}.canter_fields ON canter_terms.field_id = canter_fields.id
+               JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+               JOIN {schema}.canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id
+               WHERE canter_terms.field_id = ? AND canter_terms.value = ? GROUP BY canter_postings.term_id, canter_postings.document_id"#,
+            self.boost,
+            self.documents,
+            self.avg_documents_count,
+            self.term_frequency(),
+        )
+        .unwrap();
+
+        params.push(&self.field_id);
+        params.push(&self.value);
+
+        true
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.field_id)
+    }
+}
+
+impl TermQuery {
+    fn term_frequency(&self) -> &'static str {
+        if self.sum_payloads {
+            "SUM(canter_postings.payload)"
+        } else {
+            "COUNT(canter_postings.position)"
+        }
+    }
+}
+
+/// Matches all terms of a field which completely match a regular expression,
+/// summing their scores, which requires scanning all terms of the field.
+pub struct RegexQuery {
+    terms: ExpandedTerms,
+    pattern: String,
+}
+
+impl RegexQuery {
+    pub(crate) fn new(field: &Field, boost: f64, proximity_boost: f64, pattern: String) -> Self {
+        Self {
+            terms: ExpandedTerms::new(field, boost, proximity_boost),
+            pattern: format!("^(?:{pattern})$"),
+        }
+    }
+
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.terms = self.terms.with_bm25(bm25);
+        self
+    }
+}
+
+impl Query for RegexQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        self.terms.to_sql(
+            schema,
+            score,
+            "canter_regexp(?, canter_terms.value)",
+            sql,
+            params,
+        );
+
+        params.push(&self.pattern);
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.terms.field_id)
+    }
+}
+
+/// Matches all terms of a field starting with a prefix, summing their scores.
+pub struct PrefixQuery {
+    terms: ExpandedTerms,
+    prefix: String,
+    upper_bound: Option<String>,
+}
+
+impl PrefixQuery {
+    pub(crate) fn new(field: &Field, boost: f64, proximity_boost: f64, prefix: String) -> Self {
+        Self {
+            terms: ExpandedTerms::new(field, boost, proximity_boost),
+            upper_bound: prefix_upper_bound(&prefix),
+            prefix,
+        }
+    }
+
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.terms = self.terms.with_bm25(bm25);
+        self
+    }
+}
+
+impl Query for PrefixQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        // Matching a range of values instead of using `LIKE`, which is case-insensitive,
+        // makes use of the `UNIQUE (field_id, value)` index.
+        let condition = if self.upper_bound.is_some() {
+            "canter_terms.value >= ? AND canter_terms.value < ?"
+        } else {
+            "canter_terms.value >= ?"
+        };
+
+        self.terms.to_sql(schema, score, condition, sql, params);
+
+        params.push(&self.prefix);
+
+        if let Some(upper_bound) = &self.upper_bound {
+            params.push(upper_bound);
+        }
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.terms.field_id)
+    }
+}
+
+/// Matches all terms of a field matching a `GLOB` pattern, summing their scores.
+///
+/// The pattern is case-sensitive, and only a literal prefix before the first wildcard
+/// narrows down the terms which need to be scanned.
+pub struct WildcardQuery {
+    terms: ExpandedTerms,
+    pattern: String,
+}
+
+impl WildcardQuery {
+    pub(crate) fn new(field: &Field, boost: f64, proximity_boost: f64, pattern: String) -> Self {
+        Self {
+            terms: ExpandedTerms::new(field, boost, proximity_boost),
+            pattern,
+        }
+    }
+
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.terms = self.terms.with_bm25(bm25);
+        self
+    }
+}
+
+impl Query for WildcardQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        self.terms
+            .to_sql(schema, score, "canter_terms.value GLOB ?", sql, params);
+
+        params.push(&self.pattern);
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.terms.field_id)
+    }
+}
+
+/// Matches all terms of a field within a Levenshtein distance of a value,
+/// summing their scores.
+///
+/// The distance is computed for every term of the field whose length is close enough,
+/// so this scans the term dictionary and becomes expensive for fields with many terms.
+pub struct FuzzyQuery {
+    terms: ExpandedTerms,
+    value: String,
+    length: usize,
+    max_distance: usize,
 }
 
-impl<Q> From<Q> for Box<dyn Query>
-where
-    Q: Query + 'static,
-{
-    fn from(query: Q) -> Self {
-        Box::new(query)
-    }
-}
+impl FuzzyQuery {
+    /// The largest supported edit distance, as larger distances match too many unrelated terms.
+    pub const MAX_DISTANCE: usize = 2;
+
+    /// Creates a query matching terms within `max_distance` edits of `value`,
+    /// capping the distance at [`MAX_DISTANCE`][Self::MAX_DISTANCE].
+    pub(crate) fn new(field: &Field, boost: f64, value: String, max_distance: usize) -> Self {
+        Self {
+            terms: ExpandedTerms::new(field, boost, 0.0),
+            length: value.chars().count(),
+            value,
+            max_distance: max_distance.min(Self::MAX_DISTANCE),
+        }
+    }
+
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.terms = self.terms.with_bm25(bm25);
+        self
+    }
+}
+
+impl Query for FuzzyQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        // Comparing lengths first avoids computing the distance for most terms.
+        self.terms.to_sql(
+            schema,
+            score,
+            "ABS(LENGTH(canter_terms.value) - ?) <= ? AND canter_edit_distance(?, canter_terms.value, ?) <= ?",
+            sql,
+            params,
+        );
+
+        params.push(&self.length);
+        params.push(&self.max_distance);
+        params.push(&self.value);
+        params.push(&self.max_distance);
+        params.push(&self.max_distance);
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.terms.field_id)
+    }
+}
+
+/// Matches all terms of a field fulfilling a condition, summing their scores.
+struct ExpandedTerms {
+    field_id: i64,
+    documents: usize,
+    avg_documents_count: f64,
+    boost: f64,
+    proximity_boost: f64,
+    bm25: Option<(f64, f64)>,
+}
+
+impl ExpandedTerms {
+    fn new(field: &Field, boost: f64, proximity_boost: f64) -> Self {
+        Self {
+            field_id: field.id,
+            documents: field.documents,
+            avg_documents_count: field.avg_documents_count,
+            boost,
+            proximity_boost,
+            bm25: None,
+        }
+    }
+
+    /// Overrides `k1` and `b` of the BM25 score, cf. [`FieldConfig::bm25_k1`][crate::FieldConfig::bm25_k1].
+    fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.bm25 = bm25;
+        self
+    }
+
+    /// The parameters of `condition` must be pushed by the caller afterwards.
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        condition: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if score {
+            write!(
+                sql,
+                r#"SELECT document_id, SUM(score) AS score, 1 AS terms FROM (
+                   SELECT canter_postings.document_id AS document_id,
+                   {} * "#,
+                self.boost
+            )
+            .unwrap();
+
+            bm25_to_sql(
+                self.documents,
+                self.avg_documents_count,
+                "COUNT(canter_postings.position)",
+                self.bm25,
+                sql,
+            );
+
+            proximity_to_sql(self.proximity_boost, "canter_postings.position", sql);
+
+            sql.push_str(" AS score");
+        } else {
+            sql.push_str("SELECT DISTINCT canter_postings.document_id AS document_id");
+        }
+
+        write!(
+            sql,
+            " FROM {schema}.canter_terms\nJOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id",
+        )
+        .unwrap();
+
+        if score {
+            write!(sql, "\nJOIN {schema}.canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id").unwrap();
+        }
+
+        write!(sql, "\nWHERE canter_terms.field_id = ? AND {condition}").unwrap();
+
+        if score {
+            sql.push_str(
+                " GROUP BY canter_postings.term_id, canter_postings.document_id\n) GROUP BY document_id",
+            );
+        }
+
+        params.push(&self.field_id);
+    }
+}
+
+/// Matches documents containing a number within an inclusive range
+/// indexed via [`Writer::add_number`][crate::writer::Writer::add_number],
+/// using the boost as a constant score.
+///
+/// Numbers are indexed as one term per [`NUMERIC_PRECISION_STEP`] bits of precision,
+/// so that a range is covered by coarse terms in its middle and finer terms at its boundaries.
+pub struct RangeQuery {
+    field_id: i64,
+    boost: f64,
+    ranges: Vec<(String, String)>,
+}
+
+impl RangeQuery {
+    pub(crate) fn new(field: &Field, boost: f64, min: i64, max: i64) -> Self {
+        let mut ranges = Vec::new();
+
+        if min <= max {
+            split_numeric_range(sortable(min), sortable(max), &mut ranges);
+        }
+
+        Self {
+            field_id: field.id,
+            boost,
+            ranges,
+        }
+    }
+}
+
+impl Query for RangeQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if score {
+            write!(
+                sql,
+                "SELECT DISTINCT canter_postings.document_id AS document_id, {:?} AS score, 1 AS terms",
+                self.boost
+            )
+            .unwrap();
+        } else {
+            sql.push_str("SELECT DISTINCT canter_postings.document_id AS document_id");
+        }
+
+        write!(
+            sql,
+            " FROM {schema}.canter_terms\nJOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id\nWHERE canter_terms.field_id = ? AND (",
+        )
+        .unwrap();
+
+        params.push(&self.field_id);
+
+        if self.ranges.is_empty() {
+            sql.push('0');
+        }
+
+        for (idx, (lower, upper)) in self.ranges.iter().enumerate() {
+            if idx != 0 {
+                sql.push_str(" OR ");
+            }
+
+            sql.push_str("canter_terms.value BETWEEN ? AND ?");
+
+            params.push(lower);
+            params.push(upper);
+        }
+
+        sql.push(')');
+    }
+
+    fn field_id(&self) -> Option<i64> {
+        Some(self.field_id)
+    }
+}
+
+/// The number of bits of precision dropped between the terms indexed for a number.
+pub const NUMERIC_PRECISION_STEP: u32 = 8;
+
+/// Yields the terms indexed for `value`, from the finest to the coarsest precision.
+pub(crate) fn numeric_terms(value: i64) -> impl Iterator<Item = String> {
+    let value = sortable(value);
+
+    (0..u64::BITS)
+        .step_by(NUMERIC_PRECISION_STEP as usize)
+        .map(move |shift| numeric_term(value, shift))
+}
+
+// Flipping the sign bit makes the order of the unsigned values match the signed ones.
+fn sortable(value: i64) -> u64 {
+    (value as u64) ^ (1 << 63)
+}
+
+// The fixed width ensures that lexicographic order matches numeric order within each shift.
+fn numeric_term(value: u64, shift: u32) -> String {
+    format!("{shift:02}:{:016x}", value >> shift)
+}
+
+fn split_numeric_range(mut min: u64, mut max: u64, ranges: &mut Vec<(String, String)>) {
+    let mut shift = 0;
+
+    loop {
+        let mask = ((1 << NUMERIC_PRECISION_STEP) - 1) << shift;
+
+        let next_shift = shift + NUMERIC_PRECISION_STEP;
+
+        if next_shift >= u64::BITS {
+            ranges.push((numeric_term(min, shift), numeric_term(max, shift)));
+            break;
+        }
+
+        let diff = 1 << next_shift;
+
+        let has_lower = min & mask != 0;
+        let has_upper = max & mask != mask;
+
+        let next_min = if has_lower {
+            min.checked_add(diff)
+        } else {
+            Some(min)
+        }
+        .map(|next_min| next_min & !mask);
+
+        let next_max = if has_upper {
+            max.checked_sub(diff)
+        } else {
+            Some(max)
+        }
+        .map(|next_max| next_max & !mask);
+
+        let (Some(next_min), Some(next_max)) = (next_min, next_max) else {
+            ranges.push((numeric_term(min, shift), numeric_term(max, shift)));
+            break;
+        };
+
+        if next_min > next_max {
+            ranges.push((numeric_term(min, shift), numeric_term(max, shift)));
+            break;
+        }
+
+        if has_lower {
+            ranges.push((numeric_term(min, shift), numeric_term(min | mask, shift)));
+        }
+
+        if has_upper {
+            ranges.push((numeric_term(max & !mask, shift), numeric_term(max, shift)));
+        }
+
+        min = next_min;
+        max = next_max;
+        shift = next_shift;
+    }
+}
+
+pub struct PhraseQuery {
+    field_id: i64,
+    documents: usize,
+    avg_documents_count: f64,
+    boost: f64,
+    proximity_boost: f64,
+    values: Vec<(Vec<String>, usize)>,
+    prefix: Option<Vec<Option<String>>>,
+    slop: usize,
+    strategy: PhraseStrategy,
+    bm25: Option<(f64, f64)>,
+}
+
+impl PhraseQuery {
+    /// Each value is paired with its position as produced by the field's tokenizer,
+    /// so that gaps, e.g. due to dropped stop words, are matched as recorded during indexing.
+    /// Values sharing a position, e.g. synonyms, are alternatives of which any one has to
+    /// match at that position, but which do not all need to be present.
+    ///
+    /// If `prefix` is set, the values at the last position match all terms starting with them.
+    ///
+    /// A non-zero `slop` widens the window around the first value by that many positions,
+    /// i.e. each value can be up to its offset plus `slop` positions away from the first value
+    /// in either direction, so that e.g. two adjacent values also match in swapped order.
+    /// The score is divided by one plus the total distance from the expected positions.
+    pub(crate) fn new(
+        field: &Field,
+        boost: f64,
+        proximity_boost: f64,
+        values: Vec<(String, usize)>,
+        prefix: bool,
+        slop: usize,
+    ) -> Self {
+        let mut grouped = Vec::<(Vec<String>, usize)>::new();
+
+        for (value, position) in values {
+            match grouped.last_mut() {
+                Some((alternatives, last_position)) if *last_position == position => {
+                    if !alternatives.contains(&value) {
+                        alternatives.push(value);
+                    }
+                }
+                _ => grouped.push((vec![value], position)),
+            }
+        }
+
+        let values = grouped;
+
+        let prefix = prefix.then(|| {
+            values
+                .last()
+                .map(|(alternatives, _position)| {
+                    alternatives
+                        .iter()
+                        .map(|value| prefix_upper_bound(value))
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+
+        Self {
+            field_id: field.id,
+            documents: field.documents,
+            avg_documents_count: field.avg_documents_count,
+            boost,
+            proximity_boost,
+            values,
+            prefix,
+            slop,
+            strategy: PhraseStrategy::Joins,
+            bm25: None,
+        }
+    }
+
+    pub(crate) fn with_strategy(mut self, strategy: PhraseStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub(crate) fn with_bm25(mut self, bm25: Option<(f64, f64)>) -> Self {
+        self.bm25 = bm25;
+        self
+    }
+
+    fn offset(&self, idx: usize) -> i64 {
+        self.values[idx].1 as i64 - self.values[0].1 as i64
+    }
+
+    fn term_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        idx: usize,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        match self.strategy {
+            PhraseStrategy::Joins => {
+                sql.push('(');
+
+                self.term_rows_to_sql(schema, idx, score, sql, params);
+
+                write!(sql, ") AS term_{idx}").unwrap();
+            }
+            PhraseStrategy::Intersection => {
+                sql.push_str("(SELECT document_id, segment, GROUP_CONCAT(position) AS positions");
+
+                if score {
+                    sql.push_str(", MAX(score) AS score");
+                }
+
+                sql.push_str(" FROM (");
+
+                self.term_rows_to_sql(schema, idx, score, sql, params);
+
+                write!(
+                    sql,
+                    ") GROUP BY term_id, document_id, segment) AS term_{idx}"
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    fn term_rows_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        idx: usize,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        sql.push_str("SELECT canter_postings.term_id AS term_id, canter_postings.document_id AS document_id, canter_postings.position AS position, canter_postings.segment AS segment");
+
+        if score {
+            sql.push_str(",\n");
+
+            bm25_to_sql(
+                self.documents,
+                self.avg_documents_count,
+                "COUNT(*) OVER (PARTITION BY canter_postings.term_id, canter_postings.document_id)",
+                self.bm25,
+                sql,
+            );
+
+            sql.push_str(" AS score");
+        }
+
+        write!(
+            sql,
+            "\nFROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id",
+        )
+        .unwrap();
+
+        if score {
+            write!(sql, "\nJOIN {schema}.canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id").unwrap();
+        }
+
+        sql.push_str("\nWHERE canter_terms.field_id = ?");
+        params.push(&self.field_id);
+
+        let alternatives = &self.values[idx].0;
+
+        match &self.prefix {
+            Some(upper_bounds) if idx == self.values.len() - 1 => {
+                // Matching ranges of values makes use of the `UNIQUE (field_id, value)` index.
+                sql.push_str(" AND (");
+
+                for (jdx, (value, upper_bound)) in alternatives.iter().zip(upper_bounds).enumerate()
+                {
+                    if jdx != 0 {
+                        sql.push_str(" OR ");
+                    }
+
+                    sql.push_str("(canter_terms.value >= ?");
+                    params.push(value);
+
+                    if let Some(upper_bound) = upper_bound {
+                        sql.push_str(" AND canter_terms.value < ?");
+                        params.push(upper_bound);
+                    }
+
+                    sql.push(')');
+                }
+
+                sql.push(')');
+            }
+            _ => {
+                sql.push_str(" AND canter_terms.value IN (");
+
+                for (jdx, value) in alternatives.iter().enumerate() {
+                    if jdx != 0 {
+                        sql.push_str(", ");
+                    }
+
+                    sql.push('?');
+                    params.push(value);
+                }
+
+                sql.push(')');
+            }
+        }
+    }
+
+    /// Renders the call of `canter_phrase` matching the positions of all terms
+    /// yielding either the score factor or the earliest matching position.
+    fn phrase_to_sql(&self, min_position: bool, sql: &mut String) {
+        write!(
+            sql,
+            "canter_phrase({min_position}, {}, term_0.positions",
+            self.slop
+        )
+        .unwrap();
+
+        for idx in 1..self.values.len() {
+            write!(sql, ", {}, term_{idx}.positions", self.offset(idx)).unwrap();
+        }
+
+        sql.push(')');
+    }
+
+    /// Renders the postings of all terms joined on their positions,
+    /// yielding a row for each combination of positions which matches the phrase.
+    fn joins_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        self.term_to_sql(schema, 0, score, sql, params);
+
+        for idx in 1..self.values.len() {
+            sql.push_str("\nJOIN ");
+
+            self.term_to_sql(schema, idx, score, sql, params);
+
+            write!(
+                sql,
+                "\nON term_{idx}.document_id = term_0.document_id AND term_{idx}.segment = term_0.segment AND "
+            )
+            .unwrap();
 
-pub struct AllQuery;
+            let offset = self.offset(idx);
 
-impl Query for AllQuery {
-    fn to_sql<'query>(
+            if self.slop != 0 {
+                write!(
+                    sql,
+                    "ABS(term_{idx}.position - term_0.position) <= {}",
+                    offset + self.slop as i64
+                )
+                .unwrap();
+            } else {
+                write!(sql, "term_{idx}.position - term_0.position = {offset}").unwrap();
+            }
+        }
+    }
+
+    /// Renders the scores of a sloppy phrase, dividing the score of each combination
+    /// of matching terms by its smallest total gap before taking the best one,
+    /// as prefixes and alternatives can match different terms within the same document.
+    fn sloppy_to_sql<'query>(
         &'query self,
-        score: bool,
+        schema: &str,
         sql: &mut String,
-        _params: &mut Vec<&'query dyn ToSql>,
+        params: &mut Vec<&'query dyn ToSql>,
     ) {
-        sql.push_str(if score {
-            "SELECT DISTINCT document_id, 1 AS score, 1 as terms FROM canter_postings"
-        } else {
-            "SELECT DISTINCT document_id FROM canter_postings"
-        });
+        sql.push_str("SELECT document_id, MAX(score)");
+
+        proximity_to_sql(self.proximity_boost, "position", sql);
+
+        write!(
+            sql,
+            " AS score, {} AS terms FROM (\nSELECT term_0.document_id AS document_id, MIN(term_0.position) AS position, {} * MAX(term_0.score",
+            self.values.len(),
+            self.boost
+        )
+        .unwrap();
+
+        for idx in 1..self.values.len() {
+            write!(sql, " + term_{idx}.score").unwrap();
+        }
+
+        sql.push_str(") / MIN(1");
+
+        for idx in 1..self.values.len() {
+            write!(
+                sql,
+                " + ABS(term_{idx}.position - term_0.position - {})",
+                self.offset(idx)
+            )
+            .unwrap();
+        }
+
+        sql.push_str(") AS score FROM ");
+
+        self.joins_to_sql(schema, true, sql, params);
+
+        sql.push_str("\nGROUP BY term_0.document_id");
+
+        for idx in 0..self.values.len() {
+            write!(sql, ", term_{idx}.term_id").unwrap();
+        }
+
+        sql.push_str("\n)\nGROUP BY document_id");
     }
-}
 
-pub struct TermQuery {
-    field_id: i64,
-    documents: usize,
-    avg_documents_count: f64,
-    boost: f64,
-    value: String,
-}
+    /// Renders the first and last position of each occurrence of the phrase
+    /// ordered by document and position, which requires the `Joins` strategy.
+    pub(crate) fn spans_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        debug_assert_eq!(self.strategy, PhraseStrategy::Joins);
 
-impl TermQuery {
-    pub(crate) fn new(field: &Field, boost: f64, value: String) -> Self {
-        Self {
-            field_id: field.id,
-            documents: field.documents,
-            avg_documents_count: field.avg_documents_count,
-            boost,
-            value,
+        let positions = (0..self.values.len())
+            .map(|idx| format!("term_{idx}.position"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // The scalar `MIN` and `MAX` functions require at least two arguments.
+        if self.values.len() > 1 {
+            write!(
+                sql,
+                "SELECT DISTINCT term_0.document_id, MIN({positions}), MAX({positions}) FROM "
+            )
+            .unwrap();
+        } else {
+            sql.push_str(
+                "SELECT DISTINCT term_0.document_id, term_0.position, term_0.position FROM ",
+            );
         }
+
+        self.joins_to_sql(schema, false, sql, params);
+
+        sql.push_str("\nORDER BY 1, 2");
     }
-}
 
-impl Query for TermQuery {
-    fn to_sql<'query>(
+    fn intersection_to_sql<'query>(
         &'query self,
+        schema: &str,
         score: bool,
         sql: &mut String,
         params: &mut Vec<&'query dyn ToSql>,
@@ -69,73 +1199,175 @@ impl Query for TermQuery {
         if score {
             write!(
                 sql,
-                r#"SELECT canter_postings.document_id AS document_id,
-                   {} * canter_bm25({}, {},
-                       canter_terms.count,
-                       COUNT(canter_postings.position),
-                       canter_documents.count) AS score,
-                   1 as terms"#,
-                self.boost, self.documents, self.avg_documents_count
+                "SELECT term_0.document_id AS document_id, MAX({} * (term_0.score",
+                self.boost
             )
             .unwrap();
+
+            for idx in 1..self.values.len() {
+                write!(sql, " + term_{idx}.score").unwrap();
+            }
+
+            sql.push_str(") * ");
+
+            self.phrase_to_sql(false, sql);
+
+            sql.push(')');
+
+            if self.proximity_boost != 0.0 {
+                let mut position = String::new();
+                self.phrase_to_sql(true, &mut position);
+
+                proximity_to_sql(self.proximity_boost, &position, sql);
+            }
+
+            write!(sql, " AS score, {} AS terms FROM ", self.values.len()).unwrap();
         } else {
-            sql.push_str("SELECT canter_postings.document_id AS document_id");
+            sql.push_str("SELECT DISTINCT term_0.document_id AS document_id FROM ");
         }
 
-        sql.push_str(
-            " FROM canter_terms\nJOIN canter_postings ON canter_terms.id = canter_postings.term_id",
-        );
+        self.term_to_sql(schema, 0, score, sql, params);
 
-        if score {
-            sql.push_str("\nJOIN canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id");
+        for idx in 1..self.values.len() {
+            sql.push_str("\nJOIN ");
+
+            self.term_to_sql(schema, idx, score, sql, params);
+
+            write!(
+                sql,
+                "\nON term_{idx}.document_id = term_0.document_id AND term_{idx}.segment = term_0.segment"
+            )
+            .unwrap();
         }
 
-        write!(
-            sql,
-            "\nWHERE canter_terms.field_id = {} AND canter_terms.value = ? GROUP BY canter_postings.term_id, canter_postings.document_id",
-            self.field_id
-        )
-        .unwrap();
+        sql.push_str("\nWHERE ");
 
-        params.push(&self.value);
+        self.phrase_to_sql(false, sql);
+
+        sql.push_str(" IS NOT NULL");
+
+        if score {
+            sql.push_str("\nGROUP BY term_0.document_id");
+        }
     }
 }
 
-pub struct PhraseQuery {
-    field_id: i64,
-    documents: usize,
-    avg_documents_count: f64,
-    boost: f64,
-    values: Vec<String>,
+/// Determines how [`PhraseQuery`] matches the positions of its terms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhraseStrategy {
+    /// Joins the postings of all terms on their positions.
+    Joins,
+    /// Joins the positions of all terms within each document as lists
+    /// which are intersected by the `canter_phrase` SQL function.
+    Intersection,
 }
 
-impl PhraseQuery {
-    pub(crate) fn new(field: &Field, boost: f64, values: Vec<String>) -> Self {
-        Self {
-            field_id: field.id,
-            documents: field.documents,
-            avg_documents_count: field.avg_documents_count,
-            boost,
-            values,
+/// Computes the Levenshtein distance between `lhs` and `rhs` in characters,
+/// stopping early and yielding `max + 1` if it exceeds `max`.
+pub(crate) fn edit_distance(lhs: &str, rhs: &str, max: usize) -> usize {
+    let lhs = lhs.chars().collect::<SmallVec<[char; 32]>>();
+    let rhs = rhs.chars().collect::<SmallVec<[char; 32]>>();
+
+    if lhs.len().abs_diff(rhs.len()) > max {
+        return max + 1;
+    }
+
+    let mut prev = (0..=rhs.len()).collect::<SmallVec<[usize; 32]>>();
+    let mut curr = SmallVec::<[usize; 32]>::from_elem(0, rhs.len() + 1);
+
+    for (idx, lhs_char) in lhs.iter().enumerate() {
+        curr[0] = idx + 1;
+
+        for (jdx, rhs_char) in rhs.iter().enumerate() {
+            let substitution = prev[jdx] + usize::from(lhs_char != rhs_char);
+
+            curr[jdx + 1] = substitution.min(prev[jdx + 1] + 1).min(curr[jdx] + 1);
         }
+
+        if curr.iter().all(|distance| *distance > max) {
+            return max + 1;
+        }
+
+        swap(&mut prev, &mut curr);
+    }
+
+    prev[rhs.len()].min(max + 1)
+}
+
+/// Evaluates the positions of a phrase within a single document, yielding
+/// the best score factor and the earliest position of the first term of any match.
+///
+/// Each of the `others` terms is given by its offset relative to the first term.
+pub(crate) fn match_phrase(
+    slop: i64,
+    first: &[i64],
+    others: &[(i64, Vec<i64>)],
+) -> Option<(f64, i64)> {
+    let mut best = None;
+
+    'starts: for &start in first {
+        let mut total_gap = 0;
+
+        for (offset, positions) in others {
+            let gap = positions
+                .iter()
+                .filter(|position| {
+                    if slop != 0 {
+                        (*position - start).abs() <= offset + slop
+                    } else {
+                        *position - start == *offset
+                    }
+                })
+                .map(|position| (position - start - offset).abs())
+                .min();
+
+            match gap {
+                Some(gap) => total_gap += gap,
+                None => continue 'starts,
+            }
+        }
+
+        let factor = if slop != 0 {
+            1.0 / (1 + total_gap) as f64
+        } else {
+            1.0
+        };
+
+        best = match best {
+            Some((best_factor, first_start)) => {
+                Some((f64::max(best_factor, factor), i64::min(first_start, start)))
+            }
+            None => Some((factor, start)),
+        };
     }
+
+    best
 }
 
 impl Query for PhraseQuery {
     fn to_sql<'query>(
         &'query self,
+        schema: &str,
         score: bool,
         sql: &mut String,
         params: &mut Vec<&'query dyn ToSql>,
     ) {
         if self.values.is_empty() {
-            return AllQuery.to_sql(score, sql, params);
+            return AllQuery.to_sql(schema, score, sql, params);
+        }
+
+        if self.strategy == PhraseStrategy::Intersection {
+            return self.intersection_to_sql(schema, score, sql, params);
+        }
+
+        if score && self.slop != 0 {
+            return self.sloppy_to_sql(schema, sql, params);
         }
 
         if score {
             write!(
                 sql,
-                "SELECT term_0.document_id AS document_id, {} * (term_0.score",
+                "SELECT term_0.document_id AS document_id, MAX({} * (term_0.score",
                 self.boost
             )
             .unwrap();
@@ -144,75 +1376,177 @@ impl Query for PhraseQuery {
                 write!(sql, " + term_{idx}.score").unwrap();
             }
 
-            write!(sql, ") AS score, {} AS terms FROM", self.values.len()).unwrap();
+            sql.push_str("))");
+
+            proximity_to_sql(self.proximity_boost, "term_0.position", sql);
+
+            write!(sql, " AS score, {} AS terms FROM ", self.values.len()).unwrap();
         } else {
-            sql.push_str("SELECT term_0.document_id AS document_id FROM");
+            sql.push_str("SELECT term_0.document_id AS document_id FROM ");
         }
 
-        sql.push_str("(SELECT canter_postings.document_id AS document_id, canter_postings.position AS position");
+        self.joins_to_sql(schema, score, sql, params);
 
-        if score {
-            write!(sql, ",\ncanter_bm25({}, {}, canter_terms.count, COUNT(canter_postings.position), canter_documents.count) AS score", self.documents, self.avg_documents_count).unwrap();
-        }
+        sql.push_str("\nGROUP BY term_0.document_id");
+    }
 
-        sql.push_str(
-            "\nFROM canter_terms JOIN canter_postings ON canter_terms.id = canter_postings.term_id",
-        );
+    fn field_id(&self) -> Option<i64> {
+        Some(self.field_id)
+    }
+}
 
-        if score {
-            sql.push_str("\nJOIN canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id");
-        }
+/// Renders the BM25 score of the current term and document,
+/// passing `k1` and `b` only if they are overridden.
+fn bm25_to_sql(
+    documents: usize,
+    avg_documents_count: f64,
+    term_frequency: &str,
+    bm25: Option<(f64, f64)>,
+    sql: &mut String,
+) {
+    write!(
+        sql,
+        "canter_bm25({documents}, {avg_documents_count}, canter_terms.count, {term_frequency}, canter_documents.count"
+    )
+    .unwrap();
 
-        write!(
-            sql,
-            r#"
-WHERE canter_terms.field_id = {} AND canter_terms.value = ?
-GROUP BY canter_postings.term_id, canter_postings.document_id) AS term_0"#,
-            self.field_id
-        )
-        .unwrap();
+    if let Some((k1, b)) = bm25 {
+        write!(sql, ", {k1:?}, {b:?}").unwrap();
+    }
 
-        params.push(&self.values[0]);
+    sql.push(')');
+}
 
-        for idx in 1..self.values.len() {
-            sql.push_str("\nJOIN (SELECT canter_postings.document_id AS document_id, canter_postings.position AS position");
+/// Boosts matches by a factor decaying with their earliest position within the field.
+fn proximity_to_sql(proximity_boost: f64, position: &str, sql: &mut String) {
+    if proximity_boost != 0.0 {
+        // Debug formatting ensures a floating point literal, avoiding integer division.
+        write!(sql, " * (1 + {proximity_boost:?} / MIN({position}))").unwrap();
+    }
+}
 
-            if score {
-                write!(sql, ",\ncanter_bm25({}, {}, canter_terms.count, COUNT(canter_postings.position), canter_documents.count) AS score", self.documents, self.avg_documents_count).unwrap();
-            }
+/// Computes the smallest string larger than all strings starting with `prefix`,
+/// which does not exist if `prefix` consists only of `char::MAX`.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut upper_bound = prefix.to_owned();
 
-            sql.push_str("\nFROM canter_terms JOIN canter_postings ON canter_terms.id = canter_postings.term_id");
+    while let Some(char_) = upper_bound.pop() {
+        let next = (char_ as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
 
-            if score {
-                sql.push_str("\nJOIN canter_documents ON canter_terms.field_id = canter_documents.field_id AND canter_postings.document_id = canter_documents.document_id");
-            }
+        if let Some(next) = next {
+            upper_bound.push(next);
 
-            write!(
-                sql,
-                r#"
-WHERE canter_terms.field_id = {} AND canter_terms.value = ?
-GROUP BY canter_postings.term_id, canter_postings.document_id) AS term_{idx}
-ON term_{idx}.document_id = term_0.document_id AND term_{idx}.position - term_0.position = {idx}"#,
-                self.field_id
-            )
-            .unwrap();
+            return Some(upper_bound);
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decay {
+    Exponential,
+    Gaussian,
+}
+
+impl Decay {
+    pub(crate) fn from_sql(decay: u8) -> Self {
+        match decay {
+            0 => Self::Exponential,
+            _ => Self::Gaussian,
+        }
+    }
+
+    fn to_sql(self) -> u8 {
+        match self {
+            Self::Exponential => 0,
+            Self::Gaussian => 1,
+        }
+    }
+}
+
+/// Multiplies the score of the inner query by a factor decaying with the distance
+/// between the document's timestamp and `origin`, halving at a distance of `scale`.
+///
+/// Documents without a timestamp are not decayed.
+pub struct DecayQuery {
+    inner: Box<dyn Query>,
+    decay: Decay,
+    origin: i64,
+    scale: f64,
+}
+
+impl DecayQuery {
+    pub fn new(inner: Box<dyn Query>, decay: Decay, origin: i64, scale: f64) -> Self {
+        Self {
+            inner,
+            decay,
+            origin,
+            scale,
+        }
+    }
+}
 
-            params.push(&self.values[idx]);
+impl Query for DecayQuery {
+    fn to_sql<'query>(
+        &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        if !score {
+            return self.inner.to_sql(schema, score, sql, params);
         }
+
+        write!(
+            sql,
+            r#"SELECT decayed.document_id AS document_id,
+decayed.score * canter_decay({}, {} - canter_timestamps.timestamp, {}) AS score,
+decayed.terms AS terms
+FROM ("#,
+            self.decay.to_sql(),
+            self.origin,
+            self.scale
+        )
+        .unwrap();
+
+        self.inner.to_sql(schema, score, sql, params);
+
+        write!(
+            sql,
+            ") AS decayed\nLEFT JOIN {schema}.canter_timestamps ON decayed.document_id = canter_timestamps.document_id",
+        )
+        .unwrap();
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Occur {
     Should,
     Must,
     MustNot,
 }
 
+/// Determines the factor by which the summed scores of the matching clauses
+/// of a [`CombinedQuery`] are multiplied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coordination {
+    /// The number of terms matched by all clauses, i.e. one for each term and
+    /// as many as a phrase contains, while nested combinations count as one.
+    Terms,
+    /// The number of matching clauses divided by the number of `must` and `should` clauses.
+    Ratio,
+    /// Scores are just summed.
+    Disabled,
+}
+
 pub struct CombinedQuery {
     should: Vec<Box<dyn Query>>,
     must: Vec<Box<dyn Query>>,
     must_not: Vec<Box<dyn Query>>,
+    coordination: Coordination,
+    min_should_match: usize,
 }
 
 impl CombinedQuery {
@@ -236,14 +1570,73 @@ impl CombinedQuery {
             should,
             must,
             must_not,
+            coordination: Coordination::Terms,
+            min_should_match: 1,
         }
     }
+
+    pub fn with_coordination(mut self, coordination: Coordination) -> Self {
+        self.coordination = coordination;
+        self
+    }
+
+    /// Requires documents to match at least `min_should_match` of the should clauses
+    /// if it is larger than one. The default of one keeps should clauses optional
+    /// if there are must clauses.
+    pub fn with_min_should_match(mut self, min_should_match: usize) -> Self {
+        self.min_should_match = min_should_match;
+        self
+    }
 }
 
 impl Query for CombinedQuery {
     fn to_sql<'query>(
         &'query self,
+        schema: &str,
+        score: bool,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        self.render(schema, score, false, sql, params);
+    }
+
+    fn excluded_to_sql<'query>(
+        &'query self,
+        schema: &str,
+        sql: &mut String,
+        params: &mut Vec<&'query dyn ToSql>,
+    ) -> bool {
+        if self.must_not.is_empty() {
+            return false;
+        }
+
+        self.render(schema, false, true, sql, params);
+
+        true
+    }
+
+    fn clauses(&self) -> Vec<(Occur, &dyn Query)> {
+        let should = self.should.iter().map(|clause| (Occur::Should, &**clause));
+        let must = self.must.iter().map(|clause| (Occur::Must, &**clause));
+        let must_not = self
+            .must_not
+            .iter()
+            .map(|clause| (Occur::MustNot, &**clause));
+
+        should
+            .chain(must)
+            .chain(must_not)
+            .map(|(occur, clause)| (occur, clause as &dyn Query))
+            .collect()
+    }
+}
+
+impl CombinedQuery {
+    fn render<'query>(
+        &'query self,
+        schema: &str,
         score: bool,
+        excluded: bool,
         sql: &mut String,
         params: &mut Vec<&'query dyn ToSql>,
     ) {
@@ -263,13 +1656,29 @@ impl Query for CombinedQuery {
             }
 
             if score {
-                sql.push_str(",\n(IFNULL(clause_0.terms, 0)");
+                match self.coordination {
+                    Coordination::Terms => {
+                        sql.push_str(",\n(IFNULL(clause_0.terms, 0)");
 
-                for idx in 1..clauses {
-                    write!(sql, " + IFNULL(clause_{idx}.terms, 0)").unwrap();
+                        for idx in 1..clauses {
+                            write!(sql, " + IFNULL(clause_{idx}.terms, 0)").unwrap();
+                        }
+
+                        sql.push_str(") * ");
+                    }
+                    Coordination::Ratio => {
+                        sql.push_str(",\n((clause_0.document_id IS NOT NULL)");
+
+                        for idx in 1..clauses {
+                            write!(sql, " + (clause_{idx}.document_id IS NOT NULL)").unwrap();
+                        }
+
+                        write!(sql, ") * 1.0 / {clauses} * ").unwrap();
+                    }
+                    Coordination::Disabled => sql.push_str(",\n"),
                 }
 
-                sql.push_str(") * (IFNULL(clause_0.score, 0)");
+                sql.push_str("(IFNULL(clause_0.score, 0)");
 
                 for idx in 1..clauses {
                     write!(sql, " + IFNULL(clause_{idx}.score, 0)").unwrap();
@@ -283,14 +1692,14 @@ impl Query for CombinedQuery {
             if !self.must.is_empty() {
                 sql.push_str("\n(");
 
-                self.must[0].to_sql(score, sql, params);
+                self.must[0].to_sql(schema, score, sql, params);
 
                 sql.push_str(") AS clause_0");
 
                 for idx in 1..self.must.len() {
                     sql.push_str("\nJOIN (");
 
-                    self.must[idx].to_sql(score, sql, params);
+                    self.must[idx].to_sql(schema, score, sql, params);
 
                     write!(sql, ") AS clause_{idx} USING (document_id)").unwrap();
                 }
@@ -303,7 +1712,7 @@ impl Query for CombinedQuery {
                     sql.push_str("\n(");
                 }
 
-                self.should[0].to_sql(score, sql, params);
+                self.should[0].to_sql(schema, score, sql, params);
 
                 write!(sql, ") AS clause_{}", self.must.len()).unwrap();
                 if !self.must.is_empty() {
@@ -313,7 +1722,7 @@ impl Query for CombinedQuery {
                 for idx in 1..self.should.len() {
                     sql.push_str("\nFULL JOIN (");
 
-                    self.should[idx].to_sql(score, sql, params);
+                    self.should[idx].to_sql(schema, score, sql, params);
 
                     write!(
                         sql,
@@ -324,23 +1733,48 @@ impl Query for CombinedQuery {
                 }
             }
         } else {
-            AllQuery.to_sql(score, sql, params);
+            AllQuery.to_sql(schema, score, sql, params);
         }
 
-        if !self.must_not.is_empty() {
-            for idx in 0..self.must_not.len() {
-                sql.push_str("\nLEFT JOIN (");
+        // Each clause is rendered into its own subquery, so the aliases used by nested
+        // combinations, including those of their `must_not` clauses, cannot clash with ours.
+        for idx in 0..self.must_not.len() {
+            sql.push_str("\nLEFT JOIN (");
+
+            self.must_not[idx].to_sql(schema, false, sql, params);
+
+            write!(sql, ") AS clause_{} USING (document_id)", clauses + idx).unwrap();
+        }
+
+        let min_should_match = self.min_should_match > 1 && !self.should.is_empty();
 
-                self.must_not[idx].to_sql(false, sql, params);
+        if min_should_match {
+            let must = self.must.len();
 
-                write!(sql, ") AS clause_{} USING (document_id)", clauses + idx).unwrap();
+            write!(sql, "\nWHERE ((clause_{must}.document_id IS NOT NULL)").unwrap();
+
+            for idx in 1..self.should.len() {
+                write!(sql, " + (clause_{}.document_id IS NOT NULL)", must + idx).unwrap();
             }
 
-            write!(sql, "\nWHERE clause_{}.document_id IS NULL", clauses).unwrap();
+            write!(sql, ") >= {}", self.min_should_match).unwrap();
+        }
+
+        if !self.must_not.is_empty() {
+            sql.push_str(match (min_should_match, excluded) {
+                (false, false) => "\nWHERE (",
+                (false, true) => "\nWHERE NOT (",
+                (true, false) => " AND (",
+                (true, true) => " AND NOT (",
+            });
+
+            write!(sql, "clause_{}.document_id IS NULL", clauses).unwrap();
 
             for idx in 1..self.must_not.len() {
                 write!(sql, " AND clause_{}.document_id IS NULL", clauses + idx).unwrap();
             }
+
+            sql.push(')');
         }
     }
 }
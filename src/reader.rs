@@ -1,210 +1,2195 @@
 use std::fmt::Write;
+use std::iter::once;
+use std::mem;
 use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard, atomic::Ordering};
 
-use rusqlite::{Connection, Transaction};
+use hashbrown::{HashMap, HashSet};
+use regex::Regex;
+use rusqlite::{Connection, OptionalExtension, Statement, ToSql, params};
 use smallvec::SmallVec;
 
 use crate::{
-    Config, Fields, Index, Tokenizers,
+    Config, DataVersion, Fields, Index, Tokenizers, bm25_idf,
     error::Error,
-    query::{CombinedQuery, Occur, PhraseQuery, Query, TermQuery},
-    read_field,
-    tokenizer::ErasedTokenizer,
+    lock,
+    query::{
+        CombinedQuery, Coordination, ExistsQuery, FuzzyQuery, Occur, PhraseQuery, PrefixQuery,
+        Query, RangeQuery, RegexQuery, TermQuery, WildcardQuery,
+    },
+    quote_identifier, read_field,
+    tokenizer::{ErasedTokenizer, fold_accents},
+    try_lock,
 };
 
 impl Index {
-    pub fn read(&mut self) -> Result<Reader<'_>, Error> {
-        let txn = self.conn.transaction()?;
+    /// Starts reading the index using the first idle connection,
+    /// waiting for the main one if all are busy.
+    ///
+    /// Waiting never ends if the calling thread itself keeps all connections busy,
+    /// e.g. by holding another reader or the guard returned by [`Index::connection`].
+    /// Use [`try_read`][Self::try_read] where that cannot be ruled out.
+    pub fn read(&self) -> Result<Reader<'_>, Error> {
+        let (conn, fields, cached_data_version) = self
+            .try_acquire_connection()
+            .unwrap_or_else(|| (lock(&self.conn), &self.fields, &self.data_version));
+
+        self.begin_read(conn, fields, cached_data_version)
+    }
+
+    /// Like [`read`][Self::read], but fails with [`Error::Busy`] instead of waiting
+    /// if no connection is idle.
+    pub fn try_read(&self) -> Result<Reader<'_>, Error> {
+        let (conn, fields, cached_data_version) =
+            self.try_acquire_connection().ok_or(Error::Busy)?;
+
+        self.begin_read(conn, fields, cached_data_version)
+    }
+
+    fn begin_read<'index>(
+        &'index self,
+        conn: MutexGuard<'index, Connection>,
+        fields: &'index Mutex<Fields>,
+        cached_data_version: &'index Mutex<DataVersion>,
+    ) -> Result<Reader<'index>, Error> {
+        conn.execute_batch("BEGIN DEFERRED")?;
+
+        // `PRAGMA data_version` reflects changes made by other connections
+        // whereas the generation reflects changes made using this connection directly.
+        // Writers clear the cache themselves.
+        let data_version =
+            match conn.query_row("PRAGMA data_version", (), |row| row.get::<_, i64>(0)) {
+                Ok(data_version) => (data_version, self.generation.load(Ordering::Relaxed)),
+                Err(err) => {
+                    let _ = conn.execute_batch("ROLLBACK");
+
+                    return Err(err.into());
+                }
+            };
+
+        let mut fields = lock(fields);
+
+        {
+            let mut cached_data_version = lock(cached_data_version);
+
+            if *cached_data_version != Some(data_version) {
+                fields.clear();
+
+                *cached_data_version = Some(data_version);
+            }
+        }
 
         Ok(Reader {
-            txn,
+            conn,
             config: &self.config,
-            tokenizers: &mut self.tokenizers,
-            fields: &mut self.fields,
+            schema: &self.schema,
+            tokenizers: &self.tokenizers,
+            fields,
+            allowed_fields: None,
+            document_range: None,
+            committed: false,
         })
     }
+
+    fn try_acquire_connection(
+        &self,
+    ) -> Option<(
+        MutexGuard<'_, Connection>,
+        &Mutex<Fields>,
+        &Mutex<DataVersion>,
+    )> {
+        let connections = self.read_connections.iter().map(|read_connection| {
+            (
+                &read_connection.conn,
+                &read_connection.fields,
+                &read_connection.data_version,
+            )
+        });
+
+        for (conn, fields, data_version) in
+            once((&self.conn, &self.fields, &self.data_version)).chain(connections)
+        {
+            if let Some(conn) = try_lock(conn) {
+                return Some((conn, fields, data_version));
+            }
+        }
+
+        None
+    }
 }
 
+/// Holds a deferred transaction which is rolled back when the reader is dropped.
+///
+/// Temporary tables created using the reader, e.g. by passing `temp` to [`search`][Reader::search],
+/// can be used by subsequent searches of the same reader, but are removed by the rollback
+/// unless the reader is [committed][Reader::commit].
 pub struct Reader<'index> {
-    txn: Transaction<'index>,
+    conn: MutexGuard<'index, Connection>,
     config: &'index Config,
-    tokenizers: &'index mut Tokenizers,
-    fields: &'index mut Fields,
+    schema: &'index str,
+    tokenizers: &'index Mutex<Tokenizers>,
+    fields: MutexGuard<'index, Fields>,
+    allowed_fields: Option<HashSet<String>>,
+    document_range: Option<(i64, i64)>,
+    committed: bool,
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
 }
 
 impl Deref for Reader<'_> {
     type Target = Connection;
 
     fn deref(&self) -> &Self::Target {
-        &self.txn
+        &self.conn
     }
 }
 
 impl Reader<'_> {
+    /// The largest number of ranges yielded by [`score_histogram`][Self::score_histogram].
+    pub const MAX_BUCKETS: usize = 1024;
+
+    /// Restricts parsed queries to the given fields, rejecting clauses referencing other fields
+    /// and ignoring them when expanding the wildcard field `*`.
+    pub fn with_allowed_fields(&mut self, fields: &[&str]) -> &mut Self {
+        self.allowed_fields = Some(fields.iter().map(|&field| field.to_owned()).collect());
+        self
+    }
+
+    /// Restricts search results to documents with IDs in the inclusive range `min_id..=max_id`,
+    /// e.g. to search a single shard or time bucket without enumerating its documents.
+    pub fn with_document_range(&mut self, min_id: i64, max_id: i64) -> &mut Self {
+        self.document_range = Some((min_id, max_id));
+        self
+    }
+
+    fn is_field_allowed(&self, field_name: &str) -> bool {
+        self.allowed_fields
+            .as_ref()
+            .is_none_or(|allowed_fields| allowed_fields.contains(field_name))
+    }
+
+    /// Parses a query consisting of whitespace-separated clauses of the form `field:value`.
+    ///
+    /// * A clause prefixed with `+` must match, one prefixed with `-` must not match.
+    /// * Clauses enclosed in parentheses form a nested group which is a single clause,
+    ///   e.g. `+field:foo -(field:bar field:baz)`. Groups can be nested arbitrarily.
+    /// * The uppercase operator `NOT` is equivalent to `-` and clauses joined by `AND` must all match.
+    ///   `AND` binds tighter than `OR`, which is equivalent to just separating clauses, e.g.
+    ///   `field:foo OR field:bar AND field:baz` matches either `foo` or both `bar` and `baz`.
+    /// * A value containing whitespace must be quoted, e.g. `field:"foo bar"`.
+    /// * A trailing `*`, e.g. `field:foo*` or `field:"foo ba*"`, turns the last term into a prefix.
+    /// * A backslash escapes the following character, e.g. `field:foo\*` matches a literal `*`
+    ///   and `field:"foo \" bar"` contains a literal quote. An unquoted value ends before
+    ///   a closing parenthesis unless it is escaped like this, e.g. `field:foo\)`.
+    /// * A quoted value followed by `~N`, e.g. `field:"foo bar"~2`, allows each of its terms to be
+    ///   up to `N` positions away from where the value places it, in either direction,
+    ///   penalizing looser matches. Swapping two adjacent terms takes a distance of two.
+    /// * The field `*` matches the value in any field.
+    /// * A value prefixed with `=`, e.g. `field:=foo`, bypasses the field's tokenizer
+    ///   and is matched verbatim against the indexed terms.
+    /// * A value enclosed in slashes, e.g. `field:/fo+/`, is a regular expression
+    ///   which must match indexed terms completely. This scans all terms of the field.
+    /// * An unquoted value containing `?` or `*` other than a single trailing `*`, e.g. `field:te?t*`,
+    ///   is a wildcard pattern where `?` matches a single and `*` any number of characters.
+    ///   Like regular expressions, it is matched verbatim against indexed terms.
+    ///   A backslash turns wildcards into literal characters, e.g. `field:what\?`.
     pub fn parse(&mut self, text: &str) -> Result<Box<dyn Query>, Error> {
-        let (query, text) = self.parse_clauses(text.trim_start())?;
-        assert!(text.is_empty());
+        let mut count = 0;
+
+        let (query, text) = self.parse_clauses(text.trim_start(), &mut count)?;
+
+        if !text.is_empty() {
+            return Err(Error::UnbalancedParentheses(text.to_owned()));
+        }
 
         Ok(Box::new(query))
     }
 
+    /// Parses clauses until the end of `text` or a closing parenthesis, which is not consumed.
+    ///
+    /// `count` tracks the number of clauses over all nested groups.
     fn parse_clauses<'text>(
         &mut self,
         mut text: &'text str,
+        count: &mut usize,
     ) -> Result<(CombinedQuery, &'text str), Error> {
-        let mut clauses = Vec::new();
+        // Clauses joined by `AND` form conjunctions, each of which is combined like a single clause.
+        let mut conjunctions = Vec::<Vec<(Occur, Box<dyn Query>)>>::new();
+
+        // The text starting at the last operator which still lacks its operand.
+        let mut pending = None;
+        let mut and = false;
+        let mut not = false;
+
+        while !text.is_empty() && !text.starts_with(')') {
+            if let Some((keyword, rest)) = parse_keyword(text) {
+                match keyword {
+                    Keyword::And | Keyword::Or if pending.is_none() && !conjunctions.is_empty() => {
+                        and = keyword == Keyword::And;
+                    }
+                    Keyword::Not if !not => not = true,
+                    _ => return Err(Error::DanglingOperator(text.to_owned())),
+                }
+
+                pending = Some(text);
+                text = rest;
+                continue;
+            }
+
+            if *count == self.config.max_clauses {
+                return Err(Error::TooManyClauses(self.config.max_clauses));
+            }
+
+            *count += 1;
+
+            let (mut occur, clause, rest) = self.parse_clause(text, count)?;
+
+            if not {
+                occur = Occur::MustNot;
+            }
+
+            match conjunctions.last_mut() {
+                Some(conjunction) if and => conjunction.push((occur, clause)),
+                _ => conjunctions.push(vec![(occur, clause)]),
+            }
 
-        while !text.is_empty() {
-            let (occur, clause, rest) = self.parse_clause(text)?;
-            clauses.push((occur, clause));
+            pending = None;
+            and = false;
+            not = false;
             text = rest;
         }
 
-        Ok((CombinedQuery::new(clauses), text))
+        if let Some(pending) = pending {
+            return Err(Error::DanglingOperator(pending.to_owned()));
+        }
+
+        let single = conjunctions.len() == 1;
+
+        let mut clauses = Vec::new();
+
+        for conjunction in conjunctions {
+            if conjunction.len() == 1 {
+                clauses.extend(conjunction);
+                continue;
+            }
+
+            let conjunction = conjunction.into_iter().map(|(occur, clause)| {
+                let occur = match occur {
+                    Occur::Should => Occur::Must,
+                    occur => occur,
+                };
+
+                (occur, clause)
+            });
+
+            // A lone conjunction does not need to be nested.
+            if single {
+                clauses.extend(conjunction);
+            } else {
+                let query = CombinedQuery::new(conjunction)
+                    .with_coordination(self.config.coordination)
+                    .into();
+
+                clauses.push((Occur::Should, query));
+            }
+        }
+
+        let query = CombinedQuery::new(clauses).with_coordination(self.config.coordination);
+
+        Ok((query, text))
     }
 
     fn parse_clause<'text>(
         &mut self,
         text: &'text str,
+        count: &mut usize,
     ) -> Result<(Occur, Box<dyn Query>, &'text str), Error> {
         let (occur, text) = parse_occur(text);
+
+        if let Some(group) = text.strip_prefix('(') {
+            let (query, rest) = self.parse_clauses(group.trim_start(), count)?;
+
+            let Some(rest) = rest.strip_prefix(')') else {
+                return Err(Error::UnbalancedParentheses(text.to_owned()));
+            };
+
+            return Ok((occur, query.into(), rest.trim_start()));
+        }
+
         let (field_name, text) = parse_field_name(text)?;
+        let (value, rest) = parse_value(text)?;
 
-        let field = read_field(&self.txn, self.fields, field_name)?;
+        let query = if field_name == "*" {
+            self.parse_any_field(&value, count)?
+        } else {
+            match self.parse_field_value(field_name, &value)? {
+                Some(query) => query,
+                None => return Err(self.no_tokens(field_name, &value)?),
+            }
+        };
 
-        let tokenizer = self
-            .tokenizers
-            .get_mut(&field.tokenizer)
-            .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
+        Ok((occur, query, rest.trim_start()))
+    }
+
+    /// Expands a value for the wildcard field `*` into a query matching it in any field,
+    /// skipping fields whose tokenizer does not produce any terms for it.
+    ///
+    /// Each expanded field counts as a clause towards [`Config::max_clauses`].
+    fn parse_any_field(
+        &mut self,
+        value: &Value,
+        count: &mut usize,
+    ) -> Result<Box<dyn Query>, Error> {
+        let field_names = {
+            let schema = self.schema;
+
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT name FROM {schema}.canter_fields ORDER BY id"
+            ))?;
+
+            stmt.query_map((), |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let field_names = field_names
+            .iter()
+            .map(|field_name| field_name.as_str())
+            .filter(|field_name| self.is_field_allowed(field_name))
+            .collect::<Vec<_>>();
+
+        // The clause itself was already counted.
+        *count += field_names.len().saturating_sub(1);
+
+        if *count > self.config.max_clauses {
+            return Err(Error::TooManyClauses(self.config.max_clauses));
+        }
+
+        self.parse_fields_value(&field_names, value)
+    }
+
+    /// Analyzes `value` using the tokenizer of each of the given fields and combines
+    /// the resulting queries so that documents matching any of them are found,
+    /// e.g. to search a stemmed and an unstemmed field using their respective analysis.
+    ///
+    /// Fields whose tokenizer does not produce any terms for `value` are skipped.
+    pub fn parse_multi_field(
+        &mut self,
+        field_names: &[&str],
+        value: &str,
+    ) -> Result<Box<dyn Query>, Error> {
+        if field_names.len() > self.config.max_clauses {
+            return Err(Error::TooManyClauses(self.config.max_clauses));
+        }
+
+        let value = Value {
+            text: value.to_owned(),
+            exact: false,
+            prefix: false,
+            slop: 0,
+            regex: false,
+            wildcard: None,
+        };
+
+        self.parse_fields_value(field_names, &value)
+    }
+
+    fn parse_fields_value(
+        &mut self,
+        field_names: &[&str],
+        value: &Value,
+    ) -> Result<Box<dyn Query>, Error> {
+        let mut clauses = Vec::new();
+
+        for field_name in field_names {
+            if let Some(query) = self.parse_field_value(field_name, value)? {
+                clauses.push((Occur::Should, query));
+            }
+        }
+
+        if clauses.is_empty() {
+            return Err(Error::InvalidValue(value.text.clone()));
+        }
+
+        Ok(CombinedQuery::new(clauses)
+            .with_coordination(self.config.coordination)
+            .into())
+    }
+
+    /// Explains why `value` did not produce any tokens for the given field.
+    fn no_tokens(&mut self, field_name: &str, value: &Value) -> Result<Error, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let tokenizer = if value.exact {
+            "stub"
+        } else {
+            &field.tokenizer
+        };
+
+        let mut tokenizers = lock(self.tokenizers);
+
+        let tokenizer = tokenizers
+            .get_mut(tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(tokenizer.to_owned()))?;
+
+        let dropped_by = tokenizer
+            .erased_dropped_by(&value.text)?
+            .unwrap_or_else(|| tokenizer.erased_describe());
+
+        Ok(Error::NoTokens {
+            field: field_name.to_owned(),
+            value: value.text.clone(),
+            dropped_by,
+        })
+    }
+
+    fn parse_field_value(
+        &mut self,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Option<Box<dyn Query>>, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
+        }
+
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        // Without positions, all postings are at position zero which leaves nothing to boost.
+        let (boost, proximity_boost) =
+            self.config
+                .fields
+                .get(field_name)
+                .map_or((1.0, 0.0), |config| {
+                    let proximity_boost = if config.positions {
+                        config.proximity_boost
+                    } else {
+                        0.0
+                    };
+
+                    (config.boost, proximity_boost)
+                });
+        let boost = boost * self.config.default_boost;
+        let bm25 = self.config.field_bm25(field_name);
+
+        if value.regex {
+            let query =
+                RegexQuery::new(field, boost, proximity_boost, value.text.clone()).with_bm25(bm25);
+
+            return Ok(Some(query.into()));
+        }
+
+        let tokenizer = if value.exact {
+            "stub"
+        } else {
+            &field.tokenizer
+        };
+
+        let mut tokenizers = lock(self.tokenizers);
+
+        let tokenizer = tokenizers
+            .get_mut(tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(tokenizer.to_owned()))?;
+
+        if let Some(parts) = &value.wildcard {
+            let pattern = glob_pattern(tokenizer, parts)?;
+
+            let query = WildcardQuery::new(field, boost, proximity_boost, pattern).with_bm25(bm25);
 
-        let (mut values, rest) = parse_values(tokenizer, text)?;
+            return Ok(Some(query.into()));
+        }
+
+        let mut values = tokenize_value(tokenizer, &value.text)?;
+
+        // Other readers can use the tokenizers while this one queries the index.
+        drop(tokenizers);
+
+        let field_config = self.config.fields.get(field_name);
+
+        let expand_accents = field_config.is_some_and(|config| config.expand_accents);
+        let sum_payloads = field_config.is_some_and(|config| config.sum_payloads);
+
+        if values.len() > 1 && !field_config.is_none_or(|config| config.positions) {
+            return Err(Error::PositionsDisabled(field_name.to_owned()));
+        }
+
+        let query = match values.len() {
+            0 => return Ok(None),
+            1 if !value.prefix && expand_accents && !value.exact => {
+                let value = values.pop().unwrap().0;
+
+                let variants = {
+                    let schema = self.schema;
+
+                    let mut stmt = self.conn.prepare_cached(
+                        &format!("SELECT value FROM {schema}.canter_terms WHERE field_id = ? AND canter_fold(value) = ?"),
+                    )?;
+
+                    stmt.query_map(params![field.id, fold_accents(&value)], |row| {
+                        row.get::<_, String>(0)
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?
+                };
+
+                if variants.len() > 1 {
+                    let clauses = variants
+                        .into_iter()
+                        .map(|variant| {
+                            let query: Box<dyn Query> =
+                                TermQuery::new(field, boost, proximity_boost, variant)
+                                    .with_sum_payloads(sum_payloads)
+                                    .with_bm25(bm25)
+                                    .into();
+
+                            (Occur::Should, query)
+                        })
+                        .collect::<Vec<_>>();
+
+                    CombinedQuery::new(clauses)
+                        .with_coordination(Coordination::Disabled)
+                        .into()
+                } else {
+                    let value = variants.into_iter().next().unwrap_or(value);
+
+                    TermQuery::new(field, boost, proximity_boost, value)
+                        .with_sum_payloads(sum_payloads)
+                        .with_bm25(bm25)
+                        .into()
+                }
+            }
+            1 if !value.prefix => {
+                TermQuery::new(field, boost, proximity_boost, values.pop().unwrap().0)
+                    .with_sum_payloads(sum_payloads)
+                    .with_bm25(bm25)
+                    .into()
+            }
+            1 => PrefixQuery::new(field, boost, proximity_boost, values.pop().unwrap().0)
+                .with_bm25(bm25)
+                .into(),
+            _ => PhraseQuery::new(
+                field,
+                boost,
+                proximity_boost,
+                values.into_vec(),
+                value.prefix,
+                value.slop,
+            )
+            .with_strategy(self.config.phrase_strategy)
+            .with_bm25(bm25)
+            .into(),
+        };
+
+        Ok(Some(query))
+    }
+
+    /// Matches documents with a number in the inclusive range `min..=max`
+    /// added to the given field via [`Writer::add_number`][crate::writer::Writer::add_number].
+    pub fn range_query(
+        &mut self,
+        field_name: &str,
+        min: i64,
+        max: i64,
+    ) -> Result<Box<dyn Query>, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
+        }
+
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
 
         let boost = self
             .config
             .fields
             .get(field_name)
             .map_or(1.0, |config| config.boost);
+        let boost = boost * self.config.default_boost;
 
-        let query = match values.len() {
-            0 => return Err(Error::InvalidValue(text.to_owned())),
-            1 => TermQuery::new(field, boost, values.pop().unwrap()).into(),
-            _ => PhraseQuery::new(field, boost, values.into_vec()).into(),
-        };
-
-        Ok((occur, query, rest.trim_start()))
+        Ok(RangeQuery::new(field, boost, min, max).into())
     }
 
-    pub fn search(
-        &self,
-        query: &dyn Query,
-        limit: Option<usize>,
-        offset: Option<usize>,
-        temp: Option<&str>,
-    ) -> Result<Vec<(i64, f64)>, Error> {
-        let mut sql = String::new();
-        let mut params = Vec::new();
+    /// Matches all documents with any text added to the given field.
+    pub fn exists_query(&mut self, field_name: &str) -> Result<Box<dyn Query>, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
+        }
 
-        if let Some(temp) = temp {
-            write!(&mut sql, "CREATE TEMPORARY TABLE {temp} AS ").unwrap();
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        Ok(ExistsQuery::new(field).into())
+    }
+
+    /// Matches terms of the given field within `max_distance` edits of `value`,
+    /// which is used verbatim and capped at [`FuzzyQuery::MAX_DISTANCE`].
+    pub fn fuzzy_query(
+        &mut self,
+        field_name: &str,
+        value: &str,
+        max_distance: usize,
+    ) -> Result<Box<dyn Query>, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
         }
 
-        sql.push_str("SELECT document_id, score FROM (\n");
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let boost = self
+            .config
+            .fields
+            .get(field_name)
+            .map_or(1.0, |config| config.boost);
+        let boost = boost * self.config.default_boost;
+
+        Ok(
+            FuzzyQuery::new(field, boost, value.to_owned(), max_distance)
+                .with_bm25(self.config.field_bm25(field_name))
+                .into(),
+        )
+    }
+
+    /// Yields the positions recorded for the indexed term `value` of the given field
+    /// in a single document, e.g. to check the output of tokenizers.
+    pub fn positions(
+        &mut self,
+        field_name: &str,
+        value: &str,
+        document_id: i64,
+    ) -> Result<Vec<usize>, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let schema = self.schema;
 
-        query.to_sql(true, &mut sql, &mut params);
+        let mut stmt = self.conn.prepare_cached(&format!(
+            r#"SELECT canter_postings.position
+               FROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+               WHERE canter_terms.field_id = ? AND canter_terms.value = ? AND canter_postings.document_id = ?
+               ORDER BY canter_postings.position"#
+        ))?;
 
-        sql.push_str("\n) ORDER BY score DESC");
+        let positions = stmt
+            .query_map(params![field.id, value, document_id], |row| {
+                row.get::<_, usize>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
 
-        if let Some(limit) = limit {
-            write!(&mut sql, " LIMIT {limit}").unwrap();
+        Ok(positions)
+    }
+
+    /// Finds the occurrences of the phrase `text` within the given field, yielding the first
+    /// and last position of each occurrence for every matching document, e.g. to highlight
+    /// phrases as a whole instead of their individual terms.
+    ///
+    /// Positions are those yielded by [`positions`][Self::positions].
+    pub fn phrase_spans(&mut self, field_name: &str, text: &str) -> Result<PhraseSpans, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
         }
 
-        if let Some(offset) = offset {
-            write!(&mut sql, " OFFSET {offset}").unwrap();
+        if !self
+            .config
+            .fields
+            .get(field_name)
+            .is_none_or(|config| config.positions)
+        {
+            return Err(Error::PositionsDisabled(field_name.to_owned()));
         }
 
-        let mut results = Vec::new();
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
 
-        let mut stmt = self.txn.prepare(&sql)?;
+        let mut tokenizers = lock(self.tokenizers);
 
-        if temp.is_none() {
-            let mut rows = stmt.query(&*params)?;
+        let tokenizer = tokenizers
+            .get_mut(&field.tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
 
-            while let Some(row) = rows.next()? {
-                let document_id = row.get::<_, i64>(0)?;
-                let score = row.get::<_, f64>(1)?;
+        let values = tokenize_value(tokenizer, text)?;
 
-                results.push((document_id, score));
-            }
-        } else {
-            stmt.execute(&*params)?;
+        if values.is_empty() {
+            return Ok(Vec::new());
         }
 
-        Ok(results)
-    }
+        let query = PhraseQuery::new(field, 1.0, 0.0, values.into_vec(), false, 0);
 
-    pub fn search_all(&self, query: &dyn Query, temp: &str) -> Result<(), Error> {
         let mut sql = String::new();
         let mut params = Vec::new();
 
-        write!(&mut sql, "CREATE TEMPORARY TABLE {temp} AS ").unwrap();
+        query.spans_to_sql(self.schema, &mut sql, &mut params);
 
-        query.to_sql(false, &mut sql, &mut params);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(&*params)?;
 
-        let mut stmt = self.txn.prepare(&sql)?;
+        let mut spans = PhraseSpans::new();
 
-        stmt.execute(&*params)?;
+        while let Some(row) = rows.next()? {
+            let document_id = row.get::<_, i64>(0)?;
+            let span = (row.get::<_, usize>(1)?, row.get::<_, usize>(2)?);
 
-        Ok(())
+            match spans.last_mut() {
+                Some((last_id, last_spans)) if *last_id == document_id => last_spans.push(span),
+                _ => spans.push((document_id, vec![span])),
+            }
+        }
+
+        Ok(spans)
     }
-}
 
-fn parse_occur(text: &str) -> (Occur, &str) {
-    if let Some(text) = text.strip_prefix("+") {
-        (Occur::Must, text)
-    } else if let Some(text) = text.strip_prefix("-") {
-        (Occur::MustNot, text)
-    } else {
-        (Occur::Should, text)
+    /// Retrieves the text stored for the given field of a document, joining
+    /// multiple texts added to the same field by newlines.
+    ///
+    /// Yields `None` if no text was stored, e.g. because the field is not configured
+    /// to [`store`][crate::FieldConfig::store] text.
+    pub fn stored_text(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+    ) -> Result<Option<String>, Error> {
+        if !self.is_field_allowed(field_name) {
+            return Err(Error::FieldNotAllowed(field_name.to_owned()));
+        }
+
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let schema = self.schema;
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            r#"SELECT GROUP_CONCAT(text, char(10)) FROM (
+                   SELECT text FROM {schema}.canter_stored WHERE document_id = ? AND field_id = ? ORDER BY id
+               )"#
+        ))?;
+
+        let text = stmt.query_row(params![document_id, field.id], |row| {
+            row.get::<_, Option<String>>(0)
+        })?;
+
+        Ok(text)
     }
-}
 
-fn parse_field_name(text: &str) -> Result<(&str, &str), Error> {
-    let pos = text
-        .find(':')
-        .ok_or_else(|| Error::MissingFieldName(text.to_owned()))?;
+    /// Counts the distinct terms indexed for the given field.
+    pub fn vocabulary_size(&mut self, field_name: &str) -> Result<usize, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
 
-    let field_name = &text[..pos];
-    let text = &text[pos + 1..];
+        let schema = self.schema;
 
-    Ok((field_name, text))
-}
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT COUNT(*) FROM {schema}.canter_terms WHERE field_id = ?"
+        ))?;
 
-fn parse_values<'text>(
-    tokenizer: &mut Box<dyn ErasedTokenizer>,
-    text: &'text str,
-) -> Result<(SmallVec<[String; 1]>, &'text str), Error> {
-    let (value, text) = match text.strip_prefix("\"") {
-        Some(text) => {
-            let pos = text
-                .find('"')
-                .ok_or_else(|| Error::UnclosedQuote(text.to_owned()))?;
+        let size = stmt.query_row(params![field.id], |row| row.get::<_, usize>(0))?;
 
-            (&text[..pos], &text[pos + 1..])
-        }
-        None => {
-            let pos = text.find(char::is_whitespace).unwrap_or(text.len());
+        Ok(size)
+    }
 
-            text.split_at(pos)
-        }
-    };
+    pub fn analyze(&mut self, field_name: &str, text: &str) -> Result<Vec<String>, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let mut tokenizers = lock(self.tokenizers);
+
+        let tokenizer = tokenizers
+            .get_mut(&field.tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
+
+        let mut tokens = Vec::new();
+
+        tokenizer.erased_tokenize(text, &mut |token, _position| {
+            tokens.push(token.to_owned());
+
+            Ok(())
+        })?;
+
+        Ok(tokens)
+    }
+
+    /// Like [`analyze`][Self::analyze], but looks up the field's tokenizer only once
+    /// to analyze all of `texts`.
+    pub fn analyze_batch(
+        &mut self,
+        field_name: &str,
+        texts: &[&str],
+    ) -> Result<Vec<Vec<String>>, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let mut tokenizers = lock(self.tokenizers);
+
+        let tokenizer = tokenizers
+            .get_mut(&field.tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
+
+        texts
+            .iter()
+            .map(|text| {
+                let mut tokens = Vec::new();
+
+                tokenizer.erased_tokenize(text, &mut |token, _position| {
+                    tokens.push(token.to_owned());
+
+                    Ok(())
+                })?;
+
+                Ok(tokens)
+            })
+            .collect()
+    }
+
+    /// Yields the positions of the tokens in `text` matching any of the terms in `value`.
+    ///
+    /// If the index contains a term from `value` for the given document which is not produced
+    /// by analyzing `text`, the analyzers used for indexing and highlighting disagree
+    /// and [`Error::AnalysisMismatch`] is returned instead of silently missing it.
+    pub fn highlight(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        value: &str,
+        text: &str,
+    ) -> Result<Vec<usize>, Error> {
+        let mut terms = self.analyze(field_name, value)?;
+
+        // Repeated terms would otherwise be checked against the index as if never found.
+        terms.sort_unstable();
+        terms.dedup();
+
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let mut tokenizers = lock(self.tokenizers);
+
+        let tokenizer = tokenizers
+            .get_mut(&field.tokenizer)
+            .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
+
+        let mut positions = Vec::new();
+        let mut found = vec![false; terms.len()];
+
+        tokenizer.erased_tokenize(text, &mut |token, position| {
+            if let Some(idx) = terms.iter().position(|term| term == token) {
+                positions.push(position);
+                found[idx] = true;
+            }
+
+            Ok(())
+        })?;
+
+        let schema = self.schema;
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            r#"SELECT COUNT(*) FROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+               WHERE canter_terms.field_id = ? AND canter_terms.value = ? AND canter_postings.document_id = ?"#
+        ))?;
+
+        for (term, found) in terms.iter().zip(found) {
+            if found {
+                continue;
+            }
+
+            let indexed = stmt.query_row(params![field.id, term, document_id], |row| {
+                row.get::<_, usize>(0)
+            })?;
+
+            if indexed != 0 {
+                return Err(Error::AnalysisMismatch {
+                    field: field_name.to_owned(),
+                    term: term.clone(),
+                });
+            }
+        }
+
+        Ok(positions)
+    }
+
+    /// Commits the transaction instead of rolling it back, so that temporary tables
+    /// created using this reader remain available to later readers of the same index.
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.conn.execute_batch("COMMIT")?;
+
+        self.committed = true;
+
+        Ok(())
+    }
+
+    pub fn search(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+        temp: Option<&str>,
+    ) -> Result<Vec<(i64, f64)>, Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        if let Some(temp) = temp {
+            write!(
+                &mut sql,
+                "CREATE TEMPORARY TABLE {} AS ",
+                quote_identifier(temp)
+            )
+            .unwrap();
+        }
+
+        search_sql(
+            self.schema,
+            query,
+            limit,
+            offset,
+            self.document_range.as_ref(),
+            false,
+            &mut sql,
+            &mut params,
+        );
+
+        let mut results = Vec::new();
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        if temp.is_none() {
+            stream_results(&mut stmt, &params, &mut |document_id, score| {
+                results.push((document_id, score));
+
+                Ok(())
+            })?;
+        } else {
+            stmt.execute(&*params)?;
+        }
+
+        Ok(results)
+    }
+
+    /// Like [`search`][Self::search], but pages through the results using the score and ID
+    /// of the last result of the previous page instead of an offset, so that each page
+    /// continues exactly after the previous one even if results ahead of it change.
+    ///
+    /// Note that each page still evaluates and sorts all matching documents.
+    ///
+    /// Results are ordered by descending score and ID. The cursor for the next page
+    /// is returned if this page is full.
+    pub fn search_after(
+        &self,
+        query: &dyn Query,
+        after: Option<Cursor>,
+        limit: usize,
+    ) -> Result<Page, Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT document_id, score FROM (\n");
+
+        query.to_sql(self.schema, true, &mut sql, &mut params);
+
+        sql.push_str("\n) WHERE TRUE");
+
+        if let Some((min_id, max_id)) = &self.document_range {
+            sql.push_str(" AND document_id BETWEEN ? AND ?");
+
+            params.push(min_id);
+            params.push(max_id);
+        }
+
+        if let Some((score, document_id)) = &after {
+            sql.push_str(" AND (score, document_id) < (?, ?)");
+
+            params.push(score);
+            params.push(document_id);
+        }
+
+        write!(
+            &mut sql,
+            " ORDER BY score DESC, document_id DESC LIMIT {limit}"
+        )
+        .unwrap();
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let mut results = Vec::new();
+
+        stream_results(&mut stmt, &params, &mut |document_id, score| {
+            results.push((document_id, score));
+
+            Ok(())
+        })?;
+
+        let cursor = match results.last() {
+            Some(&(document_id, score)) if results.len() == limit => Some((score, document_id)),
+            _ => None,
+        };
+
+        Ok((results, cursor))
+    }
+
+    /// Like [`search`][Self::search], but orders the results by a value stored per document
+    /// instead of their score, falling back to their IDs to break ties.
+    ///
+    /// Documents without a stored value come last in either order.
+    pub fn search_sorted(
+        &self,
+        query: &dyn Query,
+        key: SortKey,
+        order: Order,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Vec<(i64, f64)>, Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT results.document_id, results.score FROM (\n");
+
+        query.to_sql(self.schema, true, &mut sql, &mut params);
+
+        let schema = self.schema;
+
+        match key {
+            SortKey::Key => write!(
+                sql,
+                "\n) AS results\nLEFT JOIN {schema}.canter_sort AS sort ON results.document_id = sort.document_id"
+            ),
+            SortKey::Timestamp => write!(
+                sql,
+                "\n) AS results\nLEFT JOIN (SELECT document_id, timestamp AS key FROM {schema}.canter_timestamps) AS sort ON results.document_id = sort.document_id"
+            ),
+        }
+        .unwrap();
+
+        if let Some((min_id, max_id)) = &self.document_range {
+            sql.push_str(" WHERE results.document_id BETWEEN ? AND ?");
+
+            params.push(min_id);
+            params.push(max_id);
+        }
+
+        let order = match order {
+            Order::Ascending => "ASC",
+            Order::Descending => "DESC",
+        };
+
+        write!(
+            &mut sql,
+            " ORDER BY sort.key IS NULL, sort.key {order}, results.document_id {order}"
+        )
+        .unwrap();
+
+        limit_sql(limit, offset, &mut sql);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let mut results = Vec::new();
+
+        stream_results(&mut stmt, &params, &mut |document_id, score| {
+            results.push((document_id, score));
+
+            Ok(())
+        })?;
+
+        Ok(results)
+    }
+
+    /// Like [`search`][Self::search], but divides all scores by the score of the top hit
+    /// of the query so that they fall into the range `0..=1`.
+    ///
+    /// Normalization is relative to a single query, so the results of different queries
+    /// cannot be compared using normalized scores.
+    pub fn search_normalized(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Vec<(i64, f64)>, Error> {
+        let mut results = self.search(query, limit, offset, None)?;
+
+        let max_score = match offset {
+            Some(offset) if offset != 0 => self
+                .search(query, Some(1), None, None)?
+                .first()
+                .map(|(_document_id, score)| *score),
+            _ => results.first().map(|(_document_id, score)| *score),
+        };
+
+        if let Some(max_score) = max_score.filter(|max_score| *max_score > 0.0) {
+            for (_document_id, score) in &mut results {
+                *score /= max_score;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Like [`search`][Self::search], but additionally returns the total number of matching documents
+    /// irrespective of `limit` and `offset`, e.g. to paginate results.
+    pub fn search_with_total(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<(Vec<(i64, f64)>, usize), Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        search_sql(
+            self.schema,
+            query,
+            limit,
+            offset,
+            self.document_range.as_ref(),
+            true,
+            &mut sql,
+            &mut params,
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let mut results = Vec::new();
+        let mut total = 0;
+
+        let mut rows = stmt.query(&*params)?;
+
+        while let Some(row) = rows.next()? {
+            results.push((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?));
+            total = row.get::<_, usize>(2)?;
+        }
+
+        // A page past the last result does not yield the total, so fetch it via the first result.
+        if results.is_empty() && offset.is_some_and(|offset| offset != 0) && limit != Some(0) {
+            let (_results, total) = self.search_with_total(query, Some(1), None)?;
+
+            return Ok((results, total));
+        }
+
+        Ok((results, total))
+    }
+
+    /// Counts the documents matching `query` without computing their scores,
+    /// e.g. to paginate results fetched separately via [`search`][Self::search].
+    pub fn count(&self, query: &dyn Query) -> Result<usize, Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT COUNT(*) FROM (\n");
+
+        query.to_sql(self.schema, false, &mut sql, &mut params);
+
+        sql.push_str("\n)");
+
+        if let Some((min_id, max_id)) = &self.document_range {
+            sql.push_str(" WHERE document_id BETWEEN ? AND ?");
+
+            params.push(min_id);
+            params.push(max_id);
+        }
+
+        let count = self
+            .conn
+            .query_row(&sql, &*params, |row| row.get::<_, usize>(0))?;
+
+        Ok(count)
+    }
+
+    /// Counts the documents matching `query` within each of `buckets` equally wide score ranges
+    /// spanning the lowest to the highest score, yielding the lower bound of each range with its count.
+    ///
+    /// Empty ranges are included, but no ranges are yielded if no documents match.
+    /// The number of ranges is capped at [`MAX_BUCKETS`][Self::MAX_BUCKETS].
+    pub fn score_histogram(
+        &self,
+        query: &dyn Query,
+        buckets: usize,
+    ) -> Result<Vec<(f64, usize)>, Error> {
+        if buckets == 0 {
+            return Ok(Vec::new());
+        }
+
+        let buckets = buckets.min(Self::MAX_BUCKETS);
+
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("WITH results AS (\n");
+
+        search_sql(
+            self.schema,
+            query,
+            None,
+            None,
+            self.document_range.as_ref(),
+            false,
+            &mut sql,
+            &mut params,
+        );
+
+        write!(
+            sql,
+            r#"
+), bounds AS (SELECT MIN(score) AS min, MAX(score) AS max FROM results)
+SELECT
+    CASE WHEN max > min THEN MIN(CAST((score - min) / (max - min) * {buckets} AS INTEGER), {}) ELSE 0 END AS bucket,
+    COUNT(*), MIN(min), MIN(max)
+FROM results, bounds GROUP BY bucket"#,
+            buckets - 1
+        )
+        .unwrap();
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let mut counts = vec![0; buckets];
+        let mut bounds = None;
+
+        let mut rows = stmt.query(&*params)?;
+
+        while let Some(row) = rows.next()? {
+            counts[row.get::<_, usize>(0)?] = row.get::<_, usize>(1)?;
+            bounds = Some((row.get::<_, f64>(2)?, row.get::<_, f64>(3)?));
+        }
+
+        let Some((min, max)) = bounds else {
+            return Ok(Vec::new());
+        };
+
+        let width = (max - min) / buckets as f64;
+
+        let histogram = counts
+            .into_iter()
+            .enumerate()
+            .map(|(bucket, count)| (min + bucket as f64 * width, count))
+            .collect();
+
+        Ok(histogram)
+    }
+
+    /// Like [`search`][Self::search], but yields a separate hit for each field in which
+    /// a document matched instead of a single hit with the summed score.
+    ///
+    /// Per-field scores sum the scores of the clauses scoped to that field,
+    /// ignoring any factors applied by the combination, e.g. coordination.
+    pub fn search_per_field(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Vec<(i64, String, f64)>, Error> {
+        let mut clauses = Vec::new();
+        field_clauses(query, &mut clauses);
+
+        if clauses.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str(
+            "SELECT hits.document_id, canter_fields.name, SUM(hits.score) AS score FROM (\n",
+        );
+
+        for (idx, (field_id, clause)) in clauses.iter().enumerate() {
+            if idx != 0 {
+                sql.push_str("\nUNION ALL\n");
+            }
+
+            writeln!(
+                sql,
+                "SELECT document_id, {field_id} AS field_id, score FROM ("
+            )
+            .unwrap();
+
+            clause.to_sql(self.schema, true, &mut sql, &mut params);
+
+            sql.push_str("\n)");
+        }
+
+        write!(
+            sql,
+            "\n) AS hits JOIN {}.canter_fields ON hits.field_id = canter_fields.id\nWHERE hits.document_id IN (\n",
+            self.schema
+        )
+        .unwrap();
+
+        query.to_sql(self.schema, false, &mut sql, &mut params);
+
+        sql.push_str("\n)");
+
+        if let Some((min_id, max_id)) = &self.document_range {
+            sql.push_str(" AND hits.document_id BETWEEN ? AND ?");
+
+            params.push(min_id);
+            params.push(max_id);
+        }
+
+        sql.push_str(" GROUP BY hits.document_id, hits.field_id ORDER BY score DESC");
+
+        limit_sql(limit, offset, &mut sql);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let results = stmt
+            .query_map(&*params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(results)
+    }
+
+    /// Like [`search`][Self::search], but passes results to `f` as they are produced
+    /// instead of collecting them.
+    pub fn search_stream<F>(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(i64, f64) -> Result<(), Error>,
+    {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        search_sql(
+            self.schema,
+            query,
+            limit,
+            offset,
+            self.document_range.as_ref(),
+            false,
+            &mut sql,
+            &mut params,
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        stream_results(&mut stmt, &params, &mut f)
+    }
+
+    /// Like [`search`][Self::search], but returns the string document IDs
+    /// assigned via [`Writer::add_text_str`][crate::writer::Writer::add_text_str].
+    pub fn search_str(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Vec<(String, f64)>, Error> {
+        let results = self.search(query, limit, offset, None)?;
+
+        let schema = self.schema;
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT external_id FROM {schema}.canter_docmap WHERE id = ?"
+        ))?;
+
+        results
+            .into_iter()
+            .map(|(document_id, score)| {
+                let document_id = stmt.query_row(params![document_id], |row| row.get(0))?;
+
+                Ok((document_id, score))
+            })
+            .collect()
+    }
+
+    /// Like [`search`][Self::search], but additionally counts the documents
+    /// which were removed from the results by `must_not` clauses.
+    pub fn search_with_excluded(
+        &self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<(Vec<(i64, f64)>, usize), Error> {
+        let results = self.search(query, limit, offset, None)?;
+
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT COUNT(DISTINCT document_id) FROM (\n");
+
+        if !query.excluded_to_sql(self.schema, &mut sql, &mut params) {
+            return Ok((results, 0));
+        }
+
+        sql.push_str("\n)");
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let excluded = stmt.query_row(&*params, |row| row.get::<_, usize>(0))?;
+
+        Ok((results, excluded))
+    }
+
+    /// Evaluates each top-level clause of `query` against a single document
+    /// to determine why it was excluded, treating any other query as a single `must` clause.
+    ///
+    /// Note that a combination without `must` clauses also excludes documents
+    /// not matching any of its `should` clauses.
+    pub fn explain_exclusion(
+        &self,
+        query: &dyn Query,
+        document_id: i64,
+    ) -> Result<Vec<ClauseResult>, Error> {
+        let mut clauses = query.clauses();
+
+        if clauses.is_empty() {
+            clauses.push((Occur::Must, query));
+        }
+
+        let mut results = Vec::new();
+        let mut indices = [0; 3];
+
+        for (occur, clause) in clauses {
+            let mut sql = String::new();
+            let mut params = Vec::new();
+
+            sql.push_str("SELECT EXISTS (SELECT 1 FROM (\n");
+
+            clause.to_sql(self.schema, false, &mut sql, &mut params);
+
+            sql.push_str("\n) WHERE document_id = ?)");
+
+            params.push(&document_id);
+
+            let mut stmt = self.conn.prepare(&sql)?;
+            let matches = stmt.query_row(&*params, |row| row.get::<_, bool>(0))?;
+
+            let index = &mut indices[occur as usize];
+
+            results.push(ClauseResult {
+                occur,
+                index: *index,
+                matches,
+            });
+
+            *index += 1;
+        }
+
+        Ok(results)
+    }
+
+    /// Formats a breakdown of the score of a single document, listing the score of each clause
+    /// of a combination indented below it, together with the idf, term frequency, field length,
+    /// average field length and boost entering the BM25 score of each matched term.
+    pub fn explain(&self, query: &dyn Query, document_id: i64) -> Result<String, Error> {
+        let mut explanation = String::new();
+
+        self.explain_impl(query, document_id, "query", 0, &mut explanation)?;
+
+        Ok(explanation)
+    }
+
+    fn explain_impl(
+        &self,
+        query: &dyn Query,
+        document_id: i64,
+        label: &str,
+        depth: usize,
+        explanation: &mut String,
+    ) -> Result<(), Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT score FROM (\n");
+
+        query.to_sql(self.schema, true, &mut sql, &mut params);
+
+        sql.push_str("\n) WHERE document_id = ?");
+
+        params.push(&document_id);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let score = stmt
+            .query_row(&*params, |row| row.get::<_, f64>(0))
+            .optional()?;
+
+        match score {
+            Some(score) => writeln!(
+                explanation,
+                "{:width$}{label}: {score}",
+                "",
+                width = 2 * depth
+            ),
+            None => writeln!(
+                explanation,
+                "{:width$}{label}: no match",
+                "",
+                width = 2 * depth
+            ),
+        }
+        .unwrap();
+
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("SELECT * FROM (\n");
+
+        if query.term_stats_to_sql(self.schema, &mut sql, &mut params) {
+            sql.push_str("\n) WHERE document_id = ?");
+
+            params.push(&document_id);
+
+            let mut stmt = self.conn.prepare(&sql)?;
+            let mut rows = stmt.query(&*params)?;
+
+            while let Some(row) = rows.next()? {
+                let field = row.get::<_, String>(1)?;
+                let term = row.get::<_, String>(2)?;
+                let boost = row.get::<_, f64>(3)?;
+                let documents = row.get::<_, f64>(4)?;
+                let avg_len = row.get::<_, f64>(5)?;
+                let df = row.get::<_, f64>(6)?;
+                let tf = row.get::<_, f64>(7)?;
+                let doc_len = row.get::<_, usize>(8)?;
+
+                let idf = bm25_idf(self.config, documents, df);
+
+                writeln!(
+                    explanation,
+                    "{:width$}{field}:{term}: idf {idf}, tf {tf}, doc_len {doc_len}, avg_len {avg_len}, boost {boost}",
+                    "",
+                    width = 2 * (depth + 1)
+                )
+                .unwrap();
+            }
+        }
+
+        let mut indices = [0; 3];
+
+        for (occur, clause) in query.clauses() {
+            let index = &mut indices[occur as usize];
+
+            let label = match occur {
+                Occur::Should => format!("should[{index}]"),
+                Occur::Must => format!("must[{index}]"),
+                Occur::MustNot => format!("must_not[{index}]"),
+            };
+
+            *index += 1;
+
+            self.explain_impl(clause, document_id, &label, depth + 1, explanation)?;
+        }
+
+        Ok(())
+    }
+
+    /// Formats the output of `EXPLAIN QUERY PLAN` for the SQL generated by
+    /// [`search`][Self::search], indenting each step below its parent.
+    pub fn query_plan(&self, query: &dyn Query) -> Result<String, Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        sql.push_str("EXPLAIN QUERY PLAN ");
+
+        search_sql(
+            self.schema,
+            query,
+            None,
+            None,
+            self.document_range.as_ref(),
+            false,
+            &mut sql,
+            &mut params,
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(&*params)?;
+
+        let mut depths = HashMap::new();
+        let mut plan = String::new();
+
+        while let Some(row) = rows.next()? {
+            let id = row.get::<_, i64>(0)?;
+            let parent = row.get::<_, i64>(1)?;
+            let detail = row.get::<_, String>(3)?;
+
+            let depth = depths.get(&parent).map_or(0, |depth| depth + 1);
+            depths.insert(id, depth);
+
+            writeln!(&mut plan, "{:width$}{detail}", "", width = 2 * depth).unwrap();
+        }
+
+        Ok(plan)
+    }
+
+    /// Counts the documents matching `query` for each term of the given field,
+    /// ordered by descending count.
+    pub fn facet(
+        &mut self,
+        query: &dyn Query,
+        field_name: &str,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        facet_sql(self.schema, &field.id, &mut sql, &mut params);
+
+        query.to_sql(self.schema, false, &mut sql, &mut params);
+
+        sql.push_str("\n)");
+
+        if let Some((min_id, max_id)) = &self.document_range {
+            sql.push_str(" AND canter_postings.document_id BETWEEN ? AND ?");
+
+            params.push(min_id);
+            params.push(max_id);
+        }
+
+        sql.push_str(FACET_SQL_SUFFIX);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let counts = stmt
+            .query_map(&*params, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(counts)
+    }
+
+    /// Combines [`search`][Self::search] and [`facet`][Self::facet] for each of `facet_fields`,
+    /// evaluating `query` only once by collecting its results into a temporary table.
+    pub fn search_faceted(
+        &mut self,
+        query: &dyn Query,
+        limit: Option<usize>,
+        offset: Option<usize>,
+        facet_fields: &[&str],
+    ) -> Result<(Vec<(i64, f64)>, Facets), Error> {
+        self.search(query, None, None, Some("canter_faceted"))?;
+
+        let res = self.search_faceted_impl(limit, offset, facet_fields);
+
+        self.conn.execute("DROP TABLE temp.canter_faceted", ())?;
+
+        res
+    }
+
+    fn search_faceted_impl(
+        &mut self,
+        limit: Option<usize>,
+        offset: Option<usize>,
+        facet_fields: &[&str],
+    ) -> Result<(Vec<(i64, f64)>, Facets), Error> {
+        let results = {
+            let mut sql = String::new();
+            let mut params = Vec::<&dyn ToSql>::new();
+
+            search_sql(
+                self.schema,
+                &TempTableResults,
+                limit,
+                offset,
+                None,
+                false,
+                &mut sql,
+                &mut params,
+            );
+
+            let mut stmt = self.conn.prepare(&sql)?;
+
+            let mut results = Vec::new();
+
+            stream_results(&mut stmt, &params, &mut |document_id, score| {
+                results.push((document_id, score));
+
+                Ok(())
+            })?;
+
+            results
+        };
+
+        let mut facets = HashMap::new();
+
+        for field_name in facet_fields {
+            let field = read_field(&self.conn, self.schema, &mut self.fields, field_name)?;
+
+            let mut sql = String::new();
+            let mut params = Vec::new();
+
+            facet_sql(self.schema, &field.id, &mut sql, &mut params);
+
+            sql.push_str("SELECT document_id FROM temp.canter_faceted\n)");
+
+            sql.push_str(FACET_SQL_SUFFIX);
+
+            let mut stmt = self.conn.prepare(&sql)?;
+
+            let counts = stmt
+                .query_map(&*params, |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            facets.insert((*field_name).to_owned(), counts);
+        }
+
+        Ok((results, facets))
+    }
+
+    pub fn search_all(&self, query: &dyn Query, temp: &str) -> Result<(), Error> {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        write!(
+            &mut sql,
+            "CREATE TEMPORARY TABLE {} AS ",
+            quote_identifier(temp)
+        )
+        .unwrap();
+
+        query.to_sql(self.schema, false, &mut sql, &mut params);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        stmt.execute(&*params)?;
+
+        Ok(())
+    }
+}
+
+/// The value stored per document by which [`Reader::search_sorted`] orders results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// The key set via [`Writer::add_sort_key`][crate::writer::Writer::add_sort_key].
+    Key,
+    /// The timestamp set via [`Writer::add_timestamp`][crate::writer::Writer::add_timestamp].
+    Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// The outcome of evaluating a single clause via [`Reader::explain_exclusion`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClauseResult {
+    pub occur: Occur,
+    /// The position of the clause among those with the same [`Occur`].
+    pub index: usize,
+    pub matches: bool,
+}
+
+impl ClauseResult {
+    /// Whether the clause prevents the document from matching.
+    pub fn excludes(&self) -> bool {
+        match self.occur {
+            Occur::Should => false,
+            Occur::Must => !self.matches,
+            Occur::MustNot => self.matches,
+        }
+    }
+}
+
+/// Document counts per term for each faceted field, as returned by [`Reader::search_faceted`].
+pub type Facets = HashMap<String, Vec<(String, usize)>>;
+
+/// The first and last positions of phrase occurrences per document, as returned by [`Reader::phrase_spans`].
+pub type PhraseSpans = Vec<(i64, Vec<(usize, usize)>)>;
+
+/// The score and ID of the last result of a page, cf. [`Reader::search_after`].
+pub type Cursor = (f64, i64);
+
+/// The results of a page and the cursor for the next one, as returned by [`Reader::search_after`].
+pub type Page = (Vec<(i64, f64)>, Option<Cursor>);
+
+/// Yields the scored results collected by [`Reader::search_faceted`].
+struct TempTableResults;
+
+impl Query for TempTableResults {
+    fn to_sql<'query>(
+        &'query self,
+        _schema: &str,
+        _score: bool,
+        sql: &mut String,
+        _params: &mut Vec<&'query dyn ToSql>,
+    ) {
+        sql.push_str("SELECT document_id, score FROM temp.canter_faceted");
+    }
+}
+
+/// Collects the clauses of `query` scoped to a single field which contribute to its score.
+fn field_clauses<'query>(query: &'query dyn Query, clauses: &mut Vec<(i64, &'query dyn Query)>) {
+    if let Some(field_id) = query.field_id() {
+        clauses.push((field_id, query));
+        return;
+    }
+
+    for (occur, clause) in query.clauses() {
+        if occur != Occur::MustNot {
+            field_clauses(clause, clauses);
+        }
+    }
+}
+
+fn facet_sql<'query>(
+    schema: &str,
+    field_id: &'query i64,
+    sql: &mut String,
+    params: &mut Vec<&'query dyn ToSql>,
+) {
+    write!(
+        sql,
+        r#"SELECT canter_terms.value, COUNT(DISTINCT canter_postings.document_id) AS count
+           FROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+           WHERE canter_terms.field_id = ? AND canter_postings.document_id IN (
+"#,
+    )
+    .unwrap();
+
+    params.push(field_id);
+}
+
+const FACET_SQL_SUFFIX: &str =
+    " GROUP BY canter_terms.value ORDER BY count DESC, canter_terms.value";
+
+fn stream_results(
+    stmt: &mut Statement<'_>,
+    params: &[&dyn ToSql],
+    f: &mut dyn FnMut(i64, f64) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut rows = stmt.query(params)?;
+
+    while let Some(row) = rows.next()? {
+        let document_id = row.get::<_, i64>(0)?;
+        let score = row.get::<_, f64>(1)?;
+
+        f(document_id, score)?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn search_sql<'query>(
+    schema: &str,
+    query: &'query dyn Query,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    document_range: Option<&'query (i64, i64)>,
+    total: bool,
+    sql: &mut String,
+    params: &mut Vec<&'query dyn ToSql>,
+) {
+    if total {
+        // The window is evaluated before limit and offset are applied.
+        sql.push_str("SELECT document_id, score, COUNT(*) OVER () FROM (\n");
+    } else {
+        sql.push_str("SELECT document_id, score FROM (\n");
+    }
+
+    query.to_sql(schema, true, sql, params);
+
+    sql.push_str("\n)");
+
+    if let Some((min_id, max_id)) = document_range {
+        sql.push_str(" WHERE document_id BETWEEN ? AND ?");
+
+        params.push(min_id);
+        params.push(max_id);
+    }
+
+    sql.push_str(" ORDER BY score DESC");
+
+    limit_sql(limit, offset, sql);
+}
+
+fn limit_sql(limit: Option<usize>, offset: Option<usize>, sql: &mut String) {
+    if let Some(limit) = limit {
+        write!(sql, " LIMIT {limit}").unwrap();
+    } else if offset.is_some() {
+        // SQLite requires a limit to be given together with an offset.
+        sql.push_str(" LIMIT -1");
+    }
+
+    if let Some(offset) = offset {
+        write!(sql, " OFFSET {offset}").unwrap();
+    }
+}
+
+fn parse_occur(text: &str) -> (Occur, &str) {
+    if let Some(text) = text.strip_prefix("+") {
+        (Occur::Must, text)
+    } else if let Some(text) = text.strip_prefix("-") {
+        (Occur::MustNot, text)
+    } else {
+        (Occur::Should, text)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Keyword {
+    And,
+    Or,
+    Not,
+}
+
+/// Splits off an operator if it is a separate word, so that e.g. `AND:foo` remains a clause.
+fn parse_keyword(text: &str) -> Option<(Keyword, &str)> {
+    [
+        (Keyword::And, "AND"),
+        (Keyword::Or, "OR"),
+        (Keyword::Not, "NOT"),
+    ]
+    .into_iter()
+    .find_map(|(keyword, name)| {
+        let rest = text.strip_prefix(name)?;
+
+        if rest.is_empty()
+            || rest.starts_with(|char_: char| char_.is_whitespace() || char_ == '(' || char_ == ')')
+        {
+            Some((keyword, rest.trim_start()))
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_field_name(text: &str) -> Result<(&str, &str), Error> {
+    let pos = text
+        .find(':')
+        .ok_or_else(|| Error::MissingFieldName(text.to_owned()))?;
+
+    let field_name = &text[..pos];
+    let text = &text[pos + 1..];
+
+    Ok((field_name, text))
+}
+
+type Values = SmallVec<[(String, usize); 1]>;
+
+struct Value {
+    text: String,
+    exact: bool,
+    prefix: bool,
+    slop: usize,
+    regex: bool,
+    /// The parts of a `GLOB` pattern to match instead of the value.
+    wildcard: Option<Vec<GlobPart>>,
+}
+
+enum GlobPart {
+    /// Literal text which is normalized by the field's tokenizer.
+    Literal(String),
+    /// Either `*` or `?`.
+    Wildcard(char),
+}
+
+/// Splits off the next value, which is quoted if it consists of multiple terms.
+///
+/// A leading `=` skips tokenization and a leading `/` starts a regular expression.
+/// A trailing `*` turns the last term into a prefix, while other unquoted `*` and `?`
+/// make the value a `GLOB` pattern unless a trailing `?` is the only one. Quoted values can be followed by `~N` to allow
+/// a slop of `N` positions.
+fn parse_value(text: &str) -> Result<(Value, &str), Error> {
+    let (exact, text) = match text.strip_prefix('=') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+
+    if let Some(text) = text.strip_prefix('/') {
+        return parse_regex(text);
+    }
+
+    let (quoted, text) = match text.strip_prefix('"') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+
+    let mut value = String::new();
+    let mut star = None;
+    let mut end = None;
+
+    // The value as a `GLOB` pattern, used if it contains wildcards other than a trailing `*`.
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+
+    let mut chars = text.char_indices();
+
+    while let Some((pos, char_)) = chars.next() {
+        match char_ {
+            '\\' => {
+                if let Some((_pos, char_)) = chars.next() {
+                    value.push(char_);
+                    literal.push(char_);
+                }
+            }
+            '"' if quoted => {
+                end = Some((pos, pos + 1));
+                break;
+            }
+            char_ if !quoted && (char_.is_whitespace() || char_ == ')') => {
+                end = Some((pos, pos));
+                break;
+            }
+            '*' | '?' if !quoted || char_ == '*' => {
+                if char_ == '*' {
+                    star = Some(value.len());
+                }
+                value.push(char_);
+
+                if !literal.is_empty() {
+                    parts.push(GlobPart::Literal(mem::take(&mut literal)));
+                }
+                parts.push(GlobPart::Wildcard(char_));
+            }
+            char_ => {
+                value.push(char_);
+                literal.push(char_);
+            }
+        }
+    }
+
+    let rest = match end {
+        Some((_end, rest)) => &text[rest..],
+        None if quoted => return Err(Error::UnclosedQuote(text.to_owned())),
+        None => "",
+    };
+
+    if !literal.is_empty() {
+        parts.push(GlobPart::Literal(literal));
+    }
+
+    let prefix = star.is_some_and(|star| star + 1 == value.len());
+
+    let wildcards = parts
+        .iter()
+        .filter(|part| matches!(part, GlobPart::Wildcard(_)))
+        .count();
+
+    // A single trailing `?` is more likely punctuation, e.g. in `field:Hello?`.
+    let question = wildcards == 1 && matches!(parts.last(), Some(GlobPart::Wildcard('?')));
+
+    if !quoted && !question && wildcards > usize::from(prefix) {
+        let value = Value {
+            text: value,
+            exact,
+            prefix: false,
+            slop: 0,
+            regex: false,
+            wildcard: Some(parts),
+        };
+
+        return Ok((value, rest));
+    }
+
+    if prefix {
+        value.pop();
+    }
+
+    let (slop, rest) = if quoted { parse_slop(rest)? } else { (0, rest) };
+
+    let value = Value {
+        text: value,
+        exact,
+        prefix,
+        slop,
+        regex: false,
+        wildcard: None,
+    };
+
+    Ok((value, rest))
+}
+
+/// Renders a `GLOB` pattern, normalizing each literal part using `tokenizer`
+/// if it yields a single token, e.g. so that `H?llo` matches the lower-cased `hello`.
+fn glob_pattern(
+    tokenizer: &mut Box<dyn ErasedTokenizer>,
+    parts: &[GlobPart],
+) -> Result<String, Error> {
+    let mut pattern = String::new();
+
+    for part in parts {
+        match part {
+            GlobPart::Literal(literal) => {
+                let mut tokens = tokenize_value(tokenizer, literal)?;
+
+                let literal = match tokens.len() {
+                    1 => tokens.pop().unwrap().0,
+                    _ => literal.clone(),
+                };
+
+                for char_ in literal.chars() {
+                    push_glob_literal(char_, &mut pattern);
+                }
+            }
+            GlobPart::Wildcard(char_) => pattern.push(*char_),
+        }
+    }
+
+    Ok(pattern)
+}
+
+/// Escapes characters which have a special meaning in `GLOB` patterns.
+fn push_glob_literal(char_: char, pattern: &mut String) {
+    match char_ {
+        '*' | '?' | '[' => {
+            pattern.push('[');
+            pattern.push(char_);
+            pattern.push(']');
+        }
+        char_ => pattern.push(char_),
+    }
+}
+
+fn parse_regex(text: &str) -> Result<(Value, &str), Error> {
+    let mut pattern = String::new();
+    let mut end = None;
+
+    let mut chars = text.char_indices();
+
+    while let Some((pos, char_)) = chars.next() {
+        match char_ {
+            '\\' => match chars.next() {
+                Some((_pos, '/')) => pattern.push('/'),
+                Some((_pos, char_)) => {
+                    pattern.push('\\');
+                    pattern.push(char_);
+                }
+                None => pattern.push('\\'),
+            },
+            '/' => {
+                end = Some(pos + 1);
+                break;
+            }
+            char_ => pattern.push(char_),
+        }
+    }
+
+    let Some(end) = end else {
+        return Err(Error::InvalidRegex(text.to_owned()));
+    };
+
+    if Regex::new(&pattern).is_err() {
+        return Err(Error::InvalidRegex(pattern));
+    }
+
+    let value = Value {
+        text: pattern,
+        exact: false,
+        prefix: false,
+        slop: 0,
+        regex: true,
+        wildcard: None,
+    };
+
+    Ok((value, &text[end..]))
+}
+
+fn parse_slop(text: &str) -> Result<(usize, &str), Error> {
+    let Some(text) = text.strip_prefix('~') else {
+        return Ok((0, text));
+    };
+
+    let pos = text
+        .find(|char_: char| !char_.is_ascii_digit())
+        .unwrap_or(text.len());
+
+    let slop = text[..pos]
+        .parse()
+        .map_err(|_err| Error::InvalidValue(text.to_owned()))?;
+
+    Ok((slop, &text[pos..]))
+}
 
+fn tokenize_value(tokenizer: &mut Box<dyn ErasedTokenizer>, value: &str) -> Result<Values, Error> {
     let mut values = SmallVec::new();
 
-    tokenizer.erased_tokenize(value, &mut |token| {
-        values.push(token.to_owned());
+    tokenizer.erased_tokenize(value, &mut |token, position| {
+        values.push((token.to_owned(), position));
 
         Ok(())
     })?;
 
-    Ok((values, text))
+    Ok(values)
 }
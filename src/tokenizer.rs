@@ -1,15 +1,86 @@
-use crate::{Index, error::Error};
+use std::any::type_name;
+
+use caseless::Caseless;
+use hashbrown::{HashMap, HashSet};
+use html_escape::decode_html_entities_to_string;
+use rusqlite::params;
+pub use rust_stemmers::Algorithm as Language;
+use smallvec::SmallVec;
+use unicode_normalization::{
+    UnicodeNormalization,
+    char::{decompose_canonical, is_combining_mark},
+};
+
+use crate::{Index, error::Error, get_mut, lock};
 
 impl Index {
     pub fn add_tokenizer(&mut self, name: String, tokenizer: Box<dyn ErasedTokenizer>) {
-        self.tokenizers.insert(name, tokenizer);
+        get_mut(&mut self.tokenizers).insert(name, tokenizer);
+    }
+
+    /// Removes the tokenizer registered as `name`, unless it is still used by any field.
+    pub fn remove_tokenizer(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<Box<dyn ErasedTokenizer>>, Error> {
+        let fields = {
+            let schema = &self.schema;
+            let conn = get_mut(&mut self.conn);
+
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT name FROM {schema}.canter_fields WHERE tokenizer = ? ORDER BY name"
+            ))?;
+
+            stmt.query_map(params![name], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        if !fields.is_empty() {
+            return Err(Error::TokenizerInUse {
+                name: name.to_owned(),
+                fields,
+            });
+        }
+
+        Ok(get_mut(&mut self.tokenizers).remove(name))
+    }
+
+    pub fn describe_tokenizer(&self, name: &str) -> Option<String> {
+        lock(&self.tokenizers)
+            .get(name)
+            .map(|tokenizer| tokenizer.erased_describe())
     }
 }
 
 pub trait Tokenizer {
+    /// Splits `text` into tokens passed to `f` together with their position relative to `text`.
+    ///
+    /// Positions do not need to be contiguous, e.g. a filter dropping a token leaves a gap
+    /// which phrase queries will respect, and several tokens can share a single position.
     fn tokenize<F>(&mut self, text: &str, f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>;
+        F: FnMut(&str, usize) -> Result<(), Error>;
+
+    /// Names the tokenizer for diagnostics, defaulting to its type name without module path.
+    fn describe(&self) -> String {
+        let name = type_name::<Self>();
+        let name = name.split('<').next().unwrap();
+
+        name.rsplit("::").next().unwrap().to_owned()
+    }
+
+    /// Names the stage which drops all tokens produced from `text`, if any.
+    fn dropped_by(&mut self, text: &str) -> Result<Option<String>, Error> {
+        let mut empty = true;
+
+        self.tokenize(text, |_text, _position| {
+            empty = false;
+
+            Ok(())
+        })?;
+
+        Ok(empty.then(|| self.describe()))
+    }
 
     fn chain<T>(self, tokenizer: T) -> ChainedTokenizer<Self, T>
     where
@@ -22,30 +93,42 @@ pub trait Tokenizer {
     }
 }
 
-pub trait ErasedTokenizer {
+pub trait ErasedTokenizer: Send {
     fn erased_tokenize(
         &mut self,
         text: &str,
-        f: &mut dyn FnMut(&str) -> Result<(), Error>,
+        f: &mut dyn FnMut(&str, usize) -> Result<(), Error>,
     ) -> Result<(), Error>;
+
+    fn erased_describe(&self) -> String;
+
+    fn erased_dropped_by(&mut self, text: &str) -> Result<Option<String>, Error>;
 }
 
 impl<T> ErasedTokenizer for T
 where
-    T: Tokenizer,
+    T: Tokenizer + Send,
 {
     fn erased_tokenize(
         &mut self,
         text: &str,
-        f: &mut dyn FnMut(&str) -> Result<(), Error>,
+        f: &mut dyn FnMut(&str, usize) -> Result<(), Error>,
     ) -> Result<(), Error> {
         self.tokenize(text, f)
     }
+
+    fn erased_describe(&self) -> String {
+        self.describe()
+    }
+
+    fn erased_dropped_by(&mut self, text: &str) -> Result<Option<String>, Error> {
+        self.dropped_by(text)
+    }
 }
 
 impl<T> From<T> for Box<dyn ErasedTokenizer>
 where
-    T: Tokenizer + 'static,
+    T: Tokenizer + Send + 'static,
 {
     fn from(tokenizer: T) -> Self {
         Box::new(tokenizer)
@@ -64,10 +147,58 @@ where
 {
     fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>,
+        F: FnMut(&str, usize) -> Result<(), Error>,
     {
-        self.inner
-            .tokenize(text, |text| self.outer.tokenize(text, &mut f))
+        // Tokens emitted by the outer tokenizer are positioned relative to the token
+        // produced by the inner one, so when the outer tokenizer splits a token,
+        // all subsequent positions are shifted accordingly.
+        let mut shift = 0;
+
+        self.inner.tokenize(text, |text, position| {
+            let base = position + shift;
+            let mut last = 0;
+
+            self.outer.tokenize(text, |text, position| {
+                last = last.max(position);
+
+                f(text, base + position)
+            })?;
+
+            shift += last;
+
+            Ok(())
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("{} → {}", self.inner.describe(), self.outer.describe())
+    }
+
+    fn dropped_by(&mut self, text: &str) -> Result<Option<String>, Error> {
+        let mut tokens = Vec::new();
+
+        self.inner.tokenize(text, |text, _position| {
+            tokens.push(text.to_owned());
+
+            Ok(())
+        })?;
+
+        if tokens.is_empty() {
+            return self.inner.dropped_by(text);
+        }
+
+        let mut dropped_by = None;
+
+        for token in &tokens {
+            match self.outer.dropped_by(token)? {
+                Some(stage) => {
+                    dropped_by.get_or_insert(stage);
+                }
+                None => return Ok(None),
+            }
+        }
+
+        Ok(dropped_by)
     }
 }
 
@@ -76,27 +207,229 @@ pub struct StubTokenizer;
 impl Tokenizer for StubTokenizer {
     fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>,
+        F: FnMut(&str, usize) -> Result<(), Error>,
     {
-        f(text)
+        f(text, 0)
     }
 }
 
+/// Strips tags from HTML and decodes entities like `&amp;` and `&#233;`, passing on only
+/// the visible text, and should therefore be the first stage of a chain.
+///
+/// Tags are replaced by whitespace while comments and the contents of `script` and `style`
+/// elements are dropped. A `<` without a closing `>` is kept as text together with the remainder.
+#[derive(Default)]
+pub struct HtmlStrip {
+    buf: String,
+}
+
+impl Tokenizer for HtmlStrip {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        self.buf.clear();
+        self.buf.reserve(text.len());
+
+        let mut text = text;
+
+        while let Some(start) = text.find('<') {
+            let tag = &text[start..];
+
+            let end = if tag.starts_with("<!--") {
+                tag.find("-->").map(|end| end + 3)
+            } else {
+                tag.find('>').map(|end| end + 1)
+            };
+
+            let Some(end) = end else {
+                break;
+            };
+
+            decode_html_entities_to_string(&text[..start], &mut self.buf);
+            self.buf.push(' ');
+
+            let name = tag[1..end - 1]
+                .split(|char_: char| char_.is_whitespace() || char_ == '/')
+                .next()
+                .unwrap();
+
+            text = &tag[end..];
+
+            if name.eq_ignore_ascii_case("script") || name.eq_ignore_ascii_case("style") {
+                text = skip_raw_text(text, name);
+            }
+        }
+
+        decode_html_entities_to_string(text, &mut self.buf);
+
+        f(&self.buf, 0)
+    }
+}
+
+/// Skips the contents of an element like `script` up to and including its closing tag.
+fn skip_raw_text<'a>(text: &'a str, name: &str) -> &'a str {
+    let mut rest = text;
+
+    while let Some(start) = rest.find("</") {
+        let tag = &rest[start + 2..];
+
+        if tag
+            .get(..name.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+        {
+            return tag.find('>').map_or("", |end| &tag[end + 1..]);
+        }
+
+        rest = tag;
+    }
+
+    ""
+}
+
 pub struct SplitNonAlphanumeric;
 
 impl Tokenizer for SplitNonAlphanumeric {
     fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>,
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        let tokens = text
+            .split(|char_: char| !char_.is_alphanumeric())
+            .filter(|text| !text.is_empty());
+
+        for (position, text) in tokens.enumerate() {
+            f(text, position)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Like [`SplitNonAlphanumeric`], but keeps dots between digits so that
+/// version numbers like `v2.0.1` or `1.2.3` are not split into their components.
+pub struct SplitKeepingDottedNumbers;
+
+impl Tokenizer for SplitKeepingDottedNumbers {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
     {
-        for text in text.split(|char_: char| !char_.is_alphanumeric()) {
-            if !text.is_empty() {
-                f(text)?;
+        let mut chars = text.char_indices().peekable();
+
+        let mut start = None;
+        let mut position = 0;
+        let mut prev_digit = false;
+
+        while let Some((index, char_)) = chars.next() {
+            let next_digit = chars.peek().is_some_and(|(_, next)| next.is_ascii_digit());
+
+            if char_.is_alphanumeric() || (char_ == '.' && prev_digit && next_digit) {
+                start.get_or_insert(index);
+            } else if let Some(start) = start.take() {
+                f(&text[start..index], position)?;
+                position += 1;
             }
+
+            prev_digit = char_.is_ascii_digit();
+        }
+
+        if let Some(start) = start {
+            f(&text[start..], position)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits identifiers like `getUserName` into `get`, `User` and `Name`
+/// at lower-to-upper case transitions and letter-digit boundaries.
+///
+/// Runs of upper case letters are kept together as acronyms, e.g. `HTTPServer`
+/// yields `HTTP` and `Server`. Chain [`ToLowerCase`] to normalize the sub-tokens.
+pub struct SplitCamelCase;
+
+impl Tokenizer for SplitCamelCase {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        let chars = text
+            .char_indices()
+            .collect::<SmallVec<[(usize, char); 32]>>();
+
+        let mut start = 0;
+        let mut position = 0;
+
+        for (index, window) in chars.windows(2).enumerate() {
+            let (_, prev) = window[0];
+            let (pos, curr) = window[1];
+
+            let split = (prev.is_lowercase() && curr.is_uppercase())
+                || (prev.is_alphabetic() && curr.is_numeric())
+                || (prev.is_numeric() && curr.is_alphabetic())
+                || (prev.is_uppercase()
+                    && curr.is_uppercase()
+                    && chars
+                        .get(index + 2)
+                        .is_some_and(|(_, next)| next.is_lowercase()));
+
+            if split {
+                f(&text[start..pos], position)?;
+
+                start = pos;
+                position += 1;
+            }
+        }
+
+        if start != text.len() {
+            f(&text[start..], position)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits text into sentences placed `gap` positions apart, so that chaining
+/// e.g. [`SplitNonAlphanumeric`] yields words whose phrases do not span sentences
+/// unless the slop reaches the gap.
+///
+/// Note that the gaps count towards the length of the field.
+pub struct SentenceBoundary {
+    gap: usize,
+}
+
+impl SentenceBoundary {
+    pub fn new(gap: usize) -> Self {
+        Self { gap }
+    }
+}
+
+impl Default for SentenceBoundary {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl Tokenizer for SentenceBoundary {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        let sentences = text
+            .split_inclusive(['.', '!', '?'])
+            .filter(|text| !text.trim().is_empty());
+
+        for (index, text) in sentences.enumerate() {
+            f(text, index * self.gap)?;
         }
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        format!("SentenceBoundary({})", self.gap)
+    }
 }
 
 pub struct LimitLength {
@@ -118,13 +451,201 @@ impl Default for LimitLength {
 impl Tokenizer for LimitLength {
     fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>,
+        F: FnMut(&str, usize) -> Result<(), Error>,
     {
         if text.len() > self.limit {
             return Ok(());
         }
 
-        f(text)
+        f(text, 0)
+    }
+
+    fn describe(&self) -> String {
+        format!("LimitLength({})", self.limit)
+    }
+}
+
+/// Drops tokens shorter than `min` characters, e.g. single letters which are mostly noise.
+pub struct MinLength {
+    min: usize,
+}
+
+impl MinLength {
+    pub fn new(min: usize) -> Self {
+        Self { min }
+    }
+}
+
+impl Tokenizer for MinLength {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        if text.chars().count() < self.min {
+            return Ok(());
+        }
+
+        f(text, 0)
+    }
+
+    fn describe(&self) -> String {
+        format!("MinLength({})", self.min)
+    }
+}
+
+/// Truncates tokens longer than `limit` bytes at the preceding character boundary
+/// instead of dropping them like [`LimitLength`].
+pub struct TruncateLength {
+    limit: usize,
+}
+
+impl TruncateLength {
+    pub fn new(limit: usize) -> Self {
+        Self { limit }
+    }
+}
+
+impl Default for TruncateLength {
+    fn default() -> Self {
+        Self::new(40)
+    }
+}
+
+impl Tokenizer for TruncateLength {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        if text.len() <= self.limit {
+            return f(text, 0);
+        }
+
+        let mut end = self.limit;
+
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        f(&text[..end], 0)
+    }
+
+    fn describe(&self) -> String {
+        format!("TruncateLength({})", self.limit)
+    }
+}
+
+/// Reduces tokens to their stem using the Snowball algorithm for the given language,
+/// which expects lowercase input and should therefore be chained after [`ToLowerCase`].
+///
+/// Stemming is deterministic, so terms produced during indexing and parsing agree.
+pub struct Stemmer {
+    language: Language,
+    stemmer: rust_stemmers::Stemmer,
+}
+
+impl Stemmer {
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            stemmer: rust_stemmers::Stemmer::create(language),
+        }
+    }
+}
+
+impl Tokenizer for Stemmer {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        f(&self.stemmer.stem(text), 0)
+    }
+
+    fn describe(&self) -> String {
+        format!("Stemmer({:?})", self.language)
+    }
+}
+
+/// Emits each token together with its equivalents from a map of synonyms,
+/// e.g. `automobile` in addition to `car`, all sharing the position of the original token.
+///
+/// As a field uses the same tokenizer for indexing and parsing, query values are expanded as well
+/// and match as a phrase of all equivalents at a single position. This holds for documents
+/// indexed with the same synonyms, but changing the map requires re-indexing affected documents.
+/// Expansion also inflates the number of postings, but not the length of the field.
+///
+/// Tokens are compared verbatim, so this should be chained after any normalization like [`ToLowerCase`].
+pub struct Synonyms {
+    synonyms: HashMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    pub fn new(synonyms: HashMap<String, Vec<String>>) -> Self {
+        Self { synonyms }
+    }
+}
+
+impl Tokenizer for Synonyms {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        f(text, 0)?;
+
+        if let Some(synonyms) = self.synonyms.get(text) {
+            for (index, synonym) in synonyms.iter().enumerate() {
+                // Each term can be posted only once per position.
+                if synonym == text || synonyms[..index].contains(synonym) {
+                    continue;
+                }
+
+                f(synonym, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Drops tokens contained in a set of stop words, which should be chained after
+/// any normalization like [`ToLowerCase`] as tokens are compared verbatim.
+///
+/// Dropped tokens still advance the position of subsequent tokens, so that phrase queries
+/// match stop words as gaps, e.g. `"end of the world"` matches `end world` with two positions between.
+pub struct StopWords {
+    words: HashSet<String>,
+}
+
+impl StopWords {
+    pub fn new(words: HashSet<String>) -> Self {
+        Self { words }
+    }
+
+    pub fn english() -> Self {
+        Self::new(
+            ENGLISH_STOP_WORDS
+                .iter()
+                .map(|word| (*word).to_owned())
+                .collect(),
+        )
+    }
+}
+
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+impl Tokenizer for StopWords {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        if self.words.contains(text) {
+            return Ok(());
+        }
+
+        f(text, 0)
     }
 }
 
@@ -136,12 +657,222 @@ pub struct ToLowerCase {
 impl Tokenizer for ToLowerCase {
     fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
     where
-        F: FnMut(&str) -> Result<(), Error>,
+        F: FnMut(&str, usize) -> Result<(), Error>,
     {
         self.buf.clear();
         self.buf.reserve(text.len());
         self.buf.extend(text.chars().flat_map(char::to_lowercase));
 
-        f(&self.buf)
+        f(&self.buf, 0)
+    }
+}
+
+/// Slices tokens into overlapping windows of `n` characters, e.g. `hello` into `hel`, `ell` and `llo`,
+/// passing through tokens shorter than `n` unchanged.
+///
+/// Consecutive grams are placed at consecutive positions, so that a query value
+/// spanning several grams becomes a phrase matching it as a substring.
+pub struct Ngram {
+    n: usize,
+}
+
+impl Ngram {
+    pub fn new(n: usize) -> Self {
+        assert!(n != 0);
+
+        Self { n }
+    }
+}
+
+impl Tokenizer for Ngram {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        let bounds = text
+            .char_indices()
+            .map(|(pos, _char)| pos)
+            .chain([text.len()])
+            .collect::<SmallVec<[usize; 32]>>();
+
+        if bounds.len() <= self.n {
+            return f(text, 0);
+        }
+
+        for (position, window) in bounds.windows(self.n + 1).enumerate() {
+            f(&text[window[0]..window[self.n]], position)?;
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("Ngram({})", self.n)
+    }
+}
+
+/// The Unicode normalization forms applied by [`Normalize`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+/// Applies a Unicode normalization form, so that e.g. a precomposed `é`
+/// and an `e` followed by a combining accent yield the same term.
+pub struct Normalize {
+    form: Form,
+    buf: String,
+}
+
+impl Normalize {
+    pub fn new(form: Form) -> Self {
+        Self {
+            form,
+            buf: String::new(),
+        }
+    }
+}
+
+impl Tokenizer for Normalize {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        self.buf.clear();
+        self.buf.reserve(text.len());
+
+        match self.form {
+            Form::Nfc => self.buf.extend(text.nfc()),
+            Form::Nfd => self.buf.extend(text.nfd()),
+            Form::Nfkc => self.buf.extend(text.nfkc()),
+            Form::Nfkd => self.buf.extend(text.nfkd()),
+        }
+
+        f(&self.buf, 0)
+    }
+
+    fn describe(&self) -> String {
+        format!("Normalize({:?})", self.form)
+    }
+}
+
+/// Maps accented Latin characters to their ASCII equivalents, e.g. `é` to `e` and `ß` to `ss`,
+/// passing through all other characters unchanged.
+#[derive(Default)]
+pub struct AsciiFolding {
+    buf: String,
+}
+
+impl Tokenizer for AsciiFolding {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        self.buf.clear();
+        self.buf.reserve(text.len());
+
+        for char_ in text.chars() {
+            fold_ascii(char_, &mut self.buf);
+        }
+
+        f(&self.buf, 0)
+    }
+}
+
+fn fold_ascii(char_: char, buf: &mut String) {
+    let folded = match char_ {
+        'ß' => "ss",
+        'ẞ' => "SS",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ø' => "O",
+        'ø' => "o",
+        'Ł' => "L",
+        'ł' => "l",
+        'Đ' | 'Ð' => "D",
+        'đ' | 'ð' => "d",
+        'ı' => "i",
+        _ => "",
+    };
+
+    if !folded.is_empty() {
+        buf.push_str(folded);
+        return;
+    }
+
+    // Other accented Latin characters decompose into an ASCII letter followed by combining marks.
+    if matches!(char_, '\u{c0}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}') {
+        let mut base = None;
+        let mut marks_only = true;
+
+        decompose_canonical(char_, |part| {
+            if base.is_none() {
+                base = Some(part);
+            } else if !is_combining_mark(part) {
+                marks_only = false;
+            }
+        });
+
+        if let Some(base) = base.filter(|base| base.is_ascii() && marks_only) {
+            buf.push(base);
+            return;
+        }
     }
+
+    buf.push(char_);
+}
+
+/// Applies full Unicode case folding, e.g. mapping `ß` to `ss`,
+/// which is more appropriate for case-insensitive matching than [`ToLowerCase`].
+#[derive(Default)]
+pub struct CaseFold {
+    turkic: bool,
+    buf: String,
+}
+
+impl CaseFold {
+    /// Folds `I` to dotless `ı` and dotted `İ` to `i` as appropriate for Turkish and Azerbaijani.
+    pub fn turkic() -> Self {
+        Self {
+            turkic: true,
+            buf: String::new(),
+        }
+    }
+}
+
+impl Tokenizer for CaseFold {
+    fn tokenize<F>(&mut self, text: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, usize) -> Result<(), Error>,
+    {
+        let turkic = self.turkic;
+
+        self.buf.clear();
+        self.buf.reserve(text.len());
+        self.buf.extend(
+            text.chars()
+                .map(|char_| match char_ {
+                    'I' if turkic => 'ı',
+                    'İ' if turkic => 'i',
+                    char_ => char_,
+                })
+                .default_case_fold(),
+        );
+
+        f(&self.buf, 0)
+    }
+}
+
+/// Removes accents by decomposing `text` and dropping all combining marks.
+pub(crate) fn fold_accents(text: &str) -> String {
+    text.nfd()
+        .filter(|char_| !is_combining_mark(*char_))
+        .collect()
 }
@@ -1,44 +1,404 @@
+use std::num::NonZeroUsize;
 use std::ops::Deref;
+use std::sync::mpsc::sync_channel;
+use std::thread::{available_parallelism, scope};
 
+use hashbrown::{HashMap, HashSet};
 use rusqlite::{Connection, OptionalExtension, Transaction, params};
 
-use crate::{Fields, Index, Tokenizers, error::Error, read_field};
+use crate::{
+    Config, Fields, Index, Tokenizers, error::Error, get_mut, lock, query::numeric_terms,
+    quote_identifier, read_field,
+};
 
 impl Index {
+    /// Starts rebuilding the index from scratch, removing all previously added documents.
     pub fn rewrite(&mut self) -> Result<Writer<'_>, Error> {
-        let txn = self.conn.transaction()?;
+        let writer = self.writer()?;
 
-        txn.execute_batch(
-            r#"DELETE FROM canter_terms;
-               DELETE FROM canter_postings;
-               DELETE FROM canter_documents;"#,
-        )?;
+        let schema = writer.schema;
+
+        writer.txn.execute_batch(&format!(
+            r#"DELETE FROM {schema}.canter_timestamps;
+               DELETE FROM {schema}.canter_sort;
+               DELETE FROM {schema}.canter_docmap;
+               DELETE FROM {schema}.canter_stored;"#
+        ))?;
+
+        Ok(writer)
+    }
+
+    /// Rebuilds the index from the text stored via [`Writer::add_stored_text`],
+    /// applying the current tokenizers, e.g. after they were changed.
+    pub fn reindex(&mut self) -> Result<(), Error> {
+        let mut writer = self.writer()?;
+
+        let schema = writer.schema;
+
+        let mut last_id = 0;
+
+        loop {
+            let stored = {
+                let mut stmt = writer.txn.prepare_cached(&format!(
+                    r#"SELECT canter_stored.id, canter_stored.document_id, canter_fields.name, canter_stored.text, canter_stored.segmented
+                       FROM {schema}.canter_stored JOIN {schema}.canter_fields ON canter_stored.field_id = canter_fields.id
+                       WHERE canter_stored.id > ? ORDER BY canter_stored.id LIMIT 1024"#
+                ))?;
+
+                stmt.query_map(params![last_id], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, bool>(4)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?
+            };
+
+            if stored.is_empty() {
+                break;
+            }
+
+            for (id, document_id, field_name, text, segmented) in stored {
+                // The text is already stored and must not be stored again.
+                writer.with_savepoint(|writer| {
+                    writer.add_text_impl(document_id, &field_name, &text, segmented)
+                })?;
+
+                last_id = id;
+            }
+        }
+
+        writer.commit()
+    }
+
+    /// Copies all fields and documents of `other` into this index,
+    /// shifting its document IDs by `document_offset`.
+    ///
+    /// Fields are matched by name and must use the same tokenizer.
+    /// If a shifted document ID is already present, [`Error::DocumentConflict`] is returned
+    /// and this index is left unchanged.
+    pub fn merge_from(&mut self, other: &Index, document_offset: i64) -> Result<(), Error> {
+        let schema = &self.schema;
+        let other_schema = &other.schema;
+
+        let other = lock(&other.conn);
+        let txn = get_mut(&mut self.conn).transaction()?;
+
+        let mut field_ids = HashMap::new();
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT id, name, tokenizer FROM {other_schema}.canter_fields"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                let other_id = row.get::<_, i64>(0)?;
+                let name = row.get::<_, String>(1)?;
+                let tokenizer = row.get::<_, String>(2)?;
+
+                let existing = txn
+                    .query_row(
+                        &format!("SELECT id, tokenizer FROM {schema}.canter_fields WHERE name = ?"),
+                        params![name],
+                        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+                    )
+                    .optional()?;
+
+                let id = match existing {
+                    Some((id, existing_tokenizer)) if existing_tokenizer == tokenizer => id,
+                    Some((_id, existing_tokenizer)) => {
+                        return Err(Error::FieldConflict {
+                            name,
+                            tokenizer,
+                            existing_tokenizer,
+                        });
+                    }
+                    None => {
+                        txn.execute(
+                            &format!(
+                                "INSERT INTO {schema}.canter_fields (name, tokenizer) VALUES (?, ?)"
+                            ),
+                            params![name, tokenizer],
+                        )?;
+
+                        txn.last_insert_rowid()
+                    }
+                };
 
-        let cnt = txn.query_row(
-            "SELECT COUNT(*) FROM sqlite_schema WHERE name = 'sqlite_sequence'",
+                field_ids.insert(other_id, id);
+            }
+        }
+
+        {
+            // Documents can be present in any of the per-document tables without having any text.
+            let mut stmt = other.prepare(&format!(
+                r#"SELECT document_id FROM {other_schema}.canter_documents
+                   UNION SELECT document_id FROM {other_schema}.canter_timestamps
+                   UNION SELECT document_id FROM {other_schema}.canter_sort
+                   UNION SELECT document_id FROM {other_schema}.canter_stored
+                   UNION SELECT id FROM {other_schema}.canter_docmap"#
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            let mut check = txn.prepare(&format!(
+                r#"SELECT EXISTS (SELECT 1 FROM {schema}.canter_documents WHERE document_id = ?1)
+                   OR EXISTS (SELECT 1 FROM {schema}.canter_timestamps WHERE document_id = ?1)
+                   OR EXISTS (SELECT 1 FROM {schema}.canter_sort WHERE document_id = ?1)
+                   OR EXISTS (SELECT 1 FROM {schema}.canter_stored WHERE document_id = ?1)
+                   OR EXISTS (SELECT 1 FROM {schema}.canter_docmap WHERE id = ?1)"#
+            ))?;
+
+            while let Some(row) = rows.next()? {
+                let document_id = row.get::<_, i64>(0)? + document_offset;
+
+                if check.query_row(params![document_id], |row| row.get::<_, bool>(0))? {
+                    return Err(Error::DocumentConflict(document_id));
+                }
+            }
+        }
+
+        let mut term_ids = HashMap::new();
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT id, field_id, value, count FROM {other_schema}.canter_terms"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                let other_id = row.get::<_, i64>(0)?;
+                let field_id = field_ids[&row.get::<_, i64>(1)?];
+                let value = row.get::<_, String>(2)?;
+                let count = row.get::<_, i64>(3)?;
+
+                let existing = txn
+                    .query_row(
+                        &format!(
+                            "SELECT id FROM {schema}.canter_terms WHERE field_id = ? AND value = ?"
+                        ),
+                        params![field_id, value],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .optional()?;
+
+                let id = match existing {
+                    Some(id) => {
+                        txn.execute(
+                            &format!(
+                                "UPDATE {schema}.canter_terms SET count = count + ? WHERE id = ?"
+                            ),
+                            params![count, id],
+                        )?;
+
+                        id
+                    }
+                    None => {
+                        txn.execute(
+                            &format!("INSERT INTO {schema}.canter_terms (field_id, value, count) VALUES (?, ?, ?)"),
+                            params![field_id, value, count],
+                        )?;
+
+                        txn.last_insert_rowid()
+                    }
+                };
+
+                term_ids.insert(other_id, id);
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(
+                &format!("SELECT term_id, document_id, position, segment, payload FROM {other_schema}.canter_postings"),
+            )?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                add_posting(
+                    &txn,
+                    schema,
+                    term_ids[&row.get::<_, i64>(0)?],
+                    row.get::<_, i64>(1)? + document_offset,
+                    row.get::<_, usize>(2)?,
+                    row.get::<_, usize>(3)?,
+                    row.get::<_, f64>(4)?,
+                )?;
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT field_id, document_id, count FROM {other_schema}.canter_documents"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                add_document(
+                    &txn,
+                    schema,
+                    field_ids[&row.get::<_, i64>(0)?],
+                    row.get::<_, i64>(1)? + document_offset,
+                    None,
+                    row.get::<_, usize>(2)?,
+                )?;
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT document_id, timestamp FROM {other_schema}.canter_timestamps"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                txn.execute(
+                    &format!("INSERT INTO {schema}.canter_timestamps (document_id, timestamp) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET timestamp = ?2"),
+                    params![row.get::<_, i64>(0)? + document_offset, row.get::<_, i64>(1)?],
+                )?;
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT document_id, key FROM {other_schema}.canter_sort"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                txn.execute(
+                    &format!("INSERT INTO {schema}.canter_sort (document_id, key) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET key = ?2"),
+                    params![row.get::<_, i64>(0)? + document_offset, row.get::<_, i64>(1)?],
+                )?;
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT field_id, document_id, text, segmented FROM {other_schema}.canter_stored ORDER BY id"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                txn.execute(
+                    &format!("INSERT INTO {schema}.canter_stored (field_id, document_id, text, segmented) VALUES (?, ?, ?, ?)"),
+                    params![
+                        field_ids[&row.get::<_, i64>(0)?],
+                        row.get::<_, i64>(1)? + document_offset,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, bool>(3)?
+                    ],
+                )?;
+            }
+        }
+
+        {
+            let mut stmt = other.prepare(&format!(
+                "SELECT id, external_id FROM {other_schema}.canter_docmap"
+            ))?;
+            let mut rows = stmt.query(())?;
+
+            while let Some(row) = rows.next()? {
+                txn.execute(
+                    &format!("INSERT INTO {schema}.canter_docmap (id, external_id) VALUES (?, ?)"),
+                    params![
+                        row.get::<_, i64>(0)? + document_offset,
+                        row.get::<_, String>(1)?
+                    ],
+                )?;
+            }
+        }
+
+        analyze(&txn, &self.config)?;
+
+        txn.commit()?;
+
+        get_mut(&mut self.fields).clear();
+
+        Ok(())
+    }
+
+    /// Starts modifying the index incrementally, keeping previously added documents,
+    /// which can be removed using [`Writer::delete_document`].
+    ///
+    /// Adding to a field of an existing document replaces its previous contents,
+    /// so that documents can be re-added idempotently.
+    pub fn update(&mut self) -> Result<Writer<'_>, Error> {
+        let txn = get_mut(&mut self.conn).transaction()?;
+
+        Ok(Writer {
+            txn,
+            config: &self.config,
+            schema: &self.schema,
+            tokenizers: get_mut(&mut self.tokenizers),
+            fields: get_mut(&mut self.fields),
+            replaced: HashSet::new(),
+            pending: Vec::new(),
+            prune_terms: false,
+            postings: self.config.posting_buffer.map(Vec::with_capacity),
+        })
+    }
+
+    fn writer(&mut self) -> Result<Writer<'_>, Error> {
+        let mut writer = self.update()?;
+
+        let schema = writer.schema;
+
+        // Terms are kept with a zero count so that adding them again reuses their IDs,
+        // and those which are not added again are removed on commit.
+        if writer.config.preserve_term_ids {
+            writer.txn.execute_batch(&format!(
+                r#"UPDATE {schema}.canter_terms SET count = 0;
+                   DELETE FROM {schema}.canter_postings;
+                   DELETE FROM {schema}.canter_documents;
+                   DELETE FROM {schema}.canter_field_stats;"#
+            ))?;
+
+            writer.prune_terms = true;
+
+            return Ok(writer);
+        }
+
+        writer.txn.execute_batch(&format!(
+            r#"DELETE FROM {schema}.canter_terms;
+               DELETE FROM {schema}.canter_postings;
+               DELETE FROM {schema}.canter_documents;
+               DELETE FROM {schema}.canter_field_stats;"#
+        ))?;
+
+        let cnt = writer.txn.query_row(
+            &format!("SELECT COUNT(*) FROM {schema}.sqlite_schema WHERE name = 'sqlite_sequence'"),
             (),
             |row| row.get::<_, usize>(0),
         )?;
 
         if cnt != 0 {
-            txn.execute(
-                "DELETE FROM sqlite_sequence WHERE name IN ('canter_terms', 'canter_postings', 'canter_documents')",
+            writer.txn.execute(
+                &format!("DELETE FROM {schema}.sqlite_sequence WHERE name IN ('canter_terms', 'canter_postings', 'canter_documents')"),
                 (),
             )?;
         }
 
-        Ok(Writer {
-            txn,
-            tokenizers: &mut self.tokenizers,
-            fields: &mut self.fields,
-        })
+        Ok(writer)
     }
 }
 
 pub struct Writer<'index> {
     txn: Transaction<'index>,
+    config: &'index Config,
+    schema: &'index str,
     tokenizers: &'index mut Tokenizers,
     fields: &'index mut Fields,
+    /// The fields of documents whose contents from before this writer have been removed.
+    replaced: HashSet<(i64, i64)>,
+    /// The entries of `replaced` added within the current savepoint.
+    pending: Vec<(i64, i64)>,
+    /// Whether terms left without postings by a rewrite preserving their IDs need to be removed.
+    prune_terms: bool,
+    /// The postings not yet inserted if [`Config::posting_buffer`] is set.
+    postings: Option<Vec<Posting>>,
 }
 
 impl Deref for Writer<'_> {
@@ -50,43 +410,588 @@ impl Deref for Writer<'_> {
 }
 
 impl Writer<'_> {
+    /// Adds `text` to the given field of a document, appending to any text added before
+    /// using this writer while replacing text added by previous writers.
+    ///
+    /// If this fails, e.g. due to a tokenizer error, all changes made by this call
+    /// are rolled back using a savepoint while previously added text is kept.
+    ///
+    /// The text is also stored if [`FieldConfig::store`][crate::FieldConfig::store] is set.
     pub fn add_text(
         &mut self,
         document_id: i64,
         field_name: &str,
         text: &str,
     ) -> Result<(), Error> {
-        let field = read_field(&self.txn, self.fields, field_name)?;
+        self.with_savepoint(|writer| {
+            writer.add_text_impl(document_id, field_name, text, false)?;
+
+            let config = writer.config;
+
+            if config
+                .fields
+                .get(field_name)
+                .is_some_and(|config| config.store)
+            {
+                writer.store_text(document_id, field_name, text, false)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Adds several independent values, e.g. tags, to the given field of a document.
+    ///
+    /// Each value forms its own segment, so that phrases do not match across values,
+    /// while their terms are counted as if the values were added as a single text.
+    ///
+    /// Each value is also stored if [`FieldConfig::store`][crate::FieldConfig::store] is set.
+    pub fn add_values(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        values: &[&str],
+    ) -> Result<(), Error> {
+        let store = self
+            .config
+            .fields
+            .get(field_name)
+            .is_some_and(|config| config.store);
+
+        self.with_savepoint(|writer| {
+            for value in values {
+                writer.add_text_impl(document_id, field_name, value, true)?;
+
+                if store {
+                    writer.store_text(document_id, field_name, value, true)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Adds pre-tokenized text to the given field of a document, appending to any text added before.
+    ///
+    /// Positions are relative to the start of `tokens` like those passed by tokenizers,
+    /// so gaps and repeated positions, e.g. for synonyms, are preserved.
+    ///
+    /// As there is no text to store, [`FieldConfig::store`][crate::FieldConfig::store] is ignored.
+    pub fn add_tokens(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        tokens: &[(String, usize)],
+    ) -> Result<(), Error> {
+        self.with_savepoint(|writer| {
+            let tokens = tokens
+                .iter()
+                .map(|(token, position)| (token.as_str(), *position, 1.0));
+
+            writer.add_tokens_impl(document_id, field_name, tokens)
+        })
+    }
+
+    /// Adds pre-tokenized text like [`add_tokens`][Self::add_tokens], but with a payload per token,
+    /// e.g. its importance as determined by an upstream model, which is stored with its posting.
+    ///
+    /// Scores of fields with [`FieldConfig::sum_payloads`][crate::FieldConfig::sum_payloads] set use the sum
+    /// of the payloads of a term within a document instead of its frequency. Otherwise, payloads are ignored.
+    /// Tokens added without payloads have a payload of one.
+    ///
+    /// Payloads must be finite and non-negative, otherwise [`Error::InvalidPayload`] is returned.
+    pub fn add_tokens_weighted(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        tokens: &[(String, usize, f64)],
+    ) -> Result<(), Error> {
+        if let Some((token, _, payload)) = tokens
+            .iter()
+            .find(|(_, _, payload)| !payload.is_finite() || *payload < 0.0)
+        {
+            return Err(Error::InvalidPayload {
+                token: token.clone(),
+                payload: *payload,
+            });
+        }
+
+        self.with_savepoint(|writer| {
+            let tokens = tokens
+                .iter()
+                .map(|(token, position, payload)| (token.as_str(), *position, *payload));
+
+            writer.add_tokens_impl(document_id, field_name, tokens)
+        })
+    }
+
+    fn with_savepoint<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        self.txn.execute_batch("SAVEPOINT canter_add_text")?;
+
+        let buffered = self.postings.as_ref().map_or(0, Vec::len);
+
+        match f(self) {
+            Ok(()) => {
+                self.txn.execute_batch("RELEASE canter_add_text")?;
+
+                self.pending.clear();
+
+                if self
+                    .config
+                    .posting_buffer
+                    .is_some_and(|limit| self.postings.as_ref().map_or(0, Vec::len) >= limit)
+                {
+                    self.flush()?;
+                }
+
+                Ok(())
+            }
+            Err(err) => {
+                self.txn
+                    .execute_batch("ROLLBACK TO canter_add_text; RELEASE canter_add_text")?;
+
+                for key in self.pending.drain(..) {
+                    self.replaced.remove(&key);
+                }
+
+                if let Some(postings) = &mut self.postings {
+                    postings.truncate(buffered);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    fn add_text_impl(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        text: &str,
+        segmented: bool,
+    ) -> Result<(), Error> {
+        self.index_text(document_id, field_name, text, segmented)?;
+
+        let config = self.config;
+
+        if let Some(field_config) = config.fields.get(field_name) {
+            for target in &field_config.copy_to {
+                self.index_text(document_id, target, text, segmented)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_text(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        text: &str,
+        segmented: bool,
+    ) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let field = read_field(&self.txn, schema, self.fields, field_name)?;
 
         let tokenizer = self
             .tokenizers
             .get_mut(&field.tokenizer)
             .ok_or_else(|| Error::NoSuchTokenizer(field.tokenizer.clone()))?;
 
-        let mut position = reset_position(&self.txn, field.id, document_id)?;
+        if self.replaced.insert((field.id, document_id)) {
+            self.pending.push((field.id, document_id));
 
-        tokenizer.erased_tokenize(text, &mut |token| {
-            position += 1;
+            remove_field(&self.txn, schema, field.id, document_id)?;
+        }
+
+        let previous = reset_position(&self.txn, schema, field.id, document_id)?;
+        let base = previous.unwrap_or(0);
+        let mut last = base;
+
+        // Segments are identified by their first position as it is unique within the field.
+        let segment = if segmented { base + 1 } else { 0 };
 
-            let term_id = add_term(&self.txn, field.id, token)?;
-            add_posting(&self.txn, term_id, document_id, position)?;
+        let positions = has_positions(self.config, field_name);
+
+        tokenizer.erased_tokenize(text, &mut |token, position| {
+            let position = base.saturating_add(position).saturating_add(1);
+            last = last.max(position);
+
+            if positions {
+                let term_id = add_term(&self.txn, schema, field.id, token)?;
+                buffer_posting(
+                    &self.txn,
+                    schema,
+                    &mut self.postings,
+                    (term_id, document_id, position, segment, 1.0),
+                )?;
+            } else {
+                add_presence(&self.txn, schema, field.id, document_id, token, 1.0)?;
+            }
 
             Ok(())
         })?;
 
-        add_document(&self.txn, field.id, document_id, position)?;
+        if previous.is_none() && last == base && self.config.skip_empty_fields {
+            return Ok(());
+        }
+
+        add_document(&self.txn, schema, field.id, document_id, previous, last)?;
+
+        Ok(())
+    }
+
+    fn add_tokens_impl<'a, T>(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        tokens: T,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (&'a str, usize, f64)>,
+    {
+        let schema = self.schema;
+
+        let field = read_field(&self.txn, schema, self.fields, field_name)?;
+
+        if self.replaced.insert((field.id, document_id)) {
+            self.pending.push((field.id, document_id));
+
+            remove_field(&self.txn, schema, field.id, document_id)?;
+        }
+
+        let previous = reset_position(&self.txn, schema, field.id, document_id)?;
+        let base = previous.unwrap_or(0);
+        let mut last = base;
+
+        let positions = has_positions(self.config, field_name);
+
+        for (token, position, payload) in tokens {
+            let position = base.saturating_add(position).saturating_add(1);
+            last = last.max(position);
+
+            if positions {
+                let term_id = add_term(&self.txn, schema, field.id, token)?;
+                buffer_posting(
+                    &self.txn,
+                    schema,
+                    &mut self.postings,
+                    (term_id, document_id, position, 0, payload),
+                )?;
+            } else {
+                add_presence(&self.txn, schema, field.id, document_id, token, payload)?;
+            }
+        }
+
+        if previous.is_none() && last == base && self.config.skip_empty_fields {
+            return Ok(());
+        }
+
+        add_document(&self.txn, schema, field.id, document_id, previous, last)?;
+
+        Ok(())
+    }
+
+    /// Adds the documents produced by running `source` on several worker threads,
+    /// e.g. to parallelize loading and extracting text while it is indexed on the calling thread.
+    ///
+    /// `source` is called with the index of the worker and the number of workers
+    /// and passes `(document_id, field_name, text)` to the given sink, which yields `false`
+    /// if indexing failed so that the source can stop early.
+    ///
+    /// The number of workers follows [`std::thread::available_parallelism`],
+    /// but is limited by [`Config::max_parallelism`]. A single worker runs `source`
+    /// directly on the calling thread.
+    pub fn add_many<S>(&mut self, source: S) -> Result<(), Error>
+    where
+        S: Fn(usize, usize, &mut dyn FnMut(i64, String, String) -> bool) + Sync,
+    {
+        let mut parallelism = available_parallelism().map_or(1, NonZeroUsize::get);
+
+        if let Some(max_parallelism) = self.config.max_parallelism {
+            parallelism = parallelism.min(max_parallelism.max(1));
+        }
+
+        if parallelism == 1 {
+            let mut res = Ok(());
+
+            source(0, 1, &mut |document_id, field_name, text| {
+                res = self.add_text(document_id, &field_name, &text);
+
+                res.is_ok()
+            });
+
+            return res;
+        }
+
+        let (sender, receiver) = sync_channel(parallelism);
+
+        scope(|scope| {
+            for worker in 0..parallelism {
+                let sender = sender.clone();
+                let source = &source;
+
+                scope.spawn(move || {
+                    source(worker, parallelism, &mut |document_id, field_name, text| {
+                        sender.send((document_id, field_name, text)).is_ok()
+                    });
+                });
+            }
+
+            drop(sender);
+
+            for (document_id, field_name, text) in receiver {
+                self.add_text(document_id, &field_name, &text)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Like [`add_text`][Self::add_text], but also stores the text so that it can be retrieved
+    /// using [`Reader::stored_text`][crate::reader::Reader::stored_text]
+    /// and reindexed using [`Index::reindex`].
+    pub fn add_stored_text(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        text: &str,
+    ) -> Result<(), Error> {
+        self.with_savepoint(|writer| {
+            writer.add_text_impl(document_id, field_name, text, false)?;
+
+            writer.store_text(document_id, field_name, text, false)
+        })
+    }
+
+    /// Stores `text`, remembering whether it was a separate value so that
+    /// [`Index::reindex`] adds it as its own segment again.
+    fn store_text(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        text: &str,
+        segmented: bool,
+    ) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let field = read_field(&self.txn, schema, self.fields, field_name)?;
+
+        let mut stmt = self.txn.prepare_cached(&format!(
+            "INSERT INTO {schema}.canter_stored (field_id, document_id, text, segmented) VALUES (?, ?, ?, ?)"
+        ))?;
+
+        stmt.execute(params![field.id, document_id, text, segmented])?;
+
+        Ok(())
+    }
+
+    /// Adds a number to the given field of a document so that it can be matched
+    /// by [`Reader::range_query`][crate::reader::Reader::range_query].
+    ///
+    /// The field should be reserved for numbers and each number takes up a single position.
+    pub fn add_number(
+        &mut self,
+        document_id: i64,
+        field_name: &str,
+        value: i64,
+    ) -> Result<(), Error> {
+        let tokens = numeric_terms(value)
+            .map(|term| (term, 0))
+            .collect::<Vec<_>>();
+
+        self.add_tokens(document_id, field_name, &tokens)
+    }
+
+    pub fn add_timestamp(&mut self, document_id: i64, timestamp: i64) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let mut stmt = self.txn.prepare_cached(
+            &format!("INSERT INTO {schema}.canter_timestamps (document_id, timestamp) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET timestamp = ?2"),
+        )?;
+
+        stmt.execute(params![document_id, timestamp])?;
 
         Ok(())
     }
 
-    pub fn commit(self) -> Result<(), Error> {
-        self.txn.execute_batch(
-            r#"ANALYZE canter_fields;
-               ANALYZE canter_terms;
-               ANALYZE canter_postings;
-               ANALYZE canter_documents;"#,
+    /// Inserts the postings buffered according to [`Config::posting_buffer`].
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let Some(postings) = &mut self.postings else {
+            return Ok(());
+        };
+
+        // Inserting in primary key order keeps the B-tree appends mostly local.
+        postings.sort_unstable_by_key(|&(term_id, document_id, position, ..)| {
+            (term_id, document_id, position)
+        });
+
+        for (term_id, document_id, position, segment, payload) in postings.drain(..) {
+            add_posting(
+                &self.txn,
+                schema,
+                term_id,
+                document_id,
+                position,
+                segment,
+                payload,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the key by which [`Reader::search_sorted`][crate::reader::Reader::search_sorted]
+    /// orders a document when using [`SortKey::Key`][crate::reader::SortKey::Key].
+    pub fn add_sort_key(&mut self, document_id: i64, key: i64) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let mut stmt = self.txn.prepare_cached(
+            &format!("INSERT INTO {schema}.canter_sort (document_id, key) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET key = ?2"),
+        )?;
+
+        stmt.execute(params![document_id, key])?;
+
+        Ok(())
+    }
+
+    /// Removes a document from all fields, updating term and field statistics accordingly.
+    pub fn delete_document(&mut self, document_id: i64) -> Result<(), Error> {
+        let schema = self.schema;
+
+        self.flush()?;
+
+        self.txn.execute(
+            &format!(
+                r#"UPDATE {schema}.canter_terms SET count = count - (
+                       SELECT COUNT(*) FROM {schema}.canter_postings
+                       WHERE canter_postings.term_id = canter_terms.id AND canter_postings.document_id = ?1
+                   )
+                   WHERE id IN (SELECT term_id FROM {schema}.canter_postings WHERE document_id = ?1)"#
+            ),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!(
+                r#"DELETE FROM {schema}.canter_terms
+                   WHERE count <= 0 AND id IN (SELECT term_id FROM {schema}.canter_postings WHERE document_id = ?1)"#
+            ),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_postings WHERE document_id = ?"),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!(
+                r#"UPDATE {schema}.canter_field_stats SET documents = documents - 1, total = total - (
+                       SELECT count FROM {schema}.canter_documents
+                       WHERE canter_documents.field_id = canter_field_stats.field_id AND canter_documents.document_id = ?1
+                   )
+                   WHERE field_id IN (SELECT field_id FROM {schema}.canter_documents WHERE document_id = ?1)"#
+            ),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_documents WHERE document_id = ?"),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_timestamps WHERE document_id = ?"),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_sort WHERE document_id = ?"),
+            params![document_id],
+        )?;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_stored WHERE document_id = ?"),
+            params![document_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes a field together with all its terms, postings and stored text
+    /// while leaving other fields untouched.
+    pub fn delete_field(&mut self, field_name: &str) -> Result<(), Error> {
+        let schema = self.schema;
+
+        self.flush()?;
+
+        let field_id = read_field(&self.txn, schema, self.fields, field_name)?.id;
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_postings WHERE term_id IN (SELECT id FROM {schema}.canter_terms WHERE field_id = ?)"),
+            params![field_id],
+        )?;
+
+        for table in [
+            "canter_terms",
+            "canter_documents",
+            "canter_field_stats",
+            "canter_stored",
+        ] {
+            self.txn.execute(
+                &format!("DELETE FROM {schema}.{table} WHERE field_id = ?"),
+                params![field_id],
+            )?;
+        }
+
+        self.txn.execute(
+            &format!("DELETE FROM {schema}.canter_fields WHERE id = ?"),
+            params![field_id],
         )?;
 
+        self.fields.remove(field_name);
+
+        Ok(())
+    }
+
+    /// Like [`add_text`][Self::add_text], but identifies the document using a string
+    /// which is mapped to an internal document ID via the `canter_docmap` table.
+    ///
+    /// String and integer document IDs should not be mixed within a single index.
+    pub fn add_text_str(
+        &mut self,
+        document_id: &str,
+        field_name: &str,
+        text: &str,
+    ) -> Result<(), Error> {
+        let schema = self.schema;
+
+        let document_id = map_document(&self.txn, schema, document_id)?;
+
+        self.add_text(document_id, field_name, text)
+    }
+
+    pub fn commit(mut self) -> Result<(), Error> {
+        let schema = self.schema;
+
+        self.flush()?;
+
+        if self.prune_terms {
+            self.txn.execute(
+                &format!("DELETE FROM {schema}.canter_terms WHERE count = 0"),
+                (),
+            )?;
+        }
+
+        analyze(&self.txn, self.config)?;
+
         self.txn.commit()?;
 
         self.fields.clear();
@@ -95,24 +1000,45 @@ impl Writer<'_> {
     }
 }
 
-fn add_term(conn: &Connection, field_id: i64, value: &str) -> Result<i64, Error> {
-    let mut stmt =
-        conn.prepare_cached("SELECT id FROM canter_terms WHERE field_id = ? AND value = ?")?;
+fn analyze(conn: &Connection, config: &Config) -> Result<(), Error> {
+    if let Some(analysis_limit) = config.analysis_limit {
+        conn.pragma_update(None, "analysis_limit", analysis_limit)?;
+    }
+
+    let schema = quote_identifier(&config.schema);
+
+    conn.execute_batch(&format!(
+        r#"ANALYZE {schema}.canter_fields;
+           ANALYZE {schema}.canter_terms;
+           ANALYZE {schema}.canter_postings;
+           ANALYZE {schema}.canter_documents;
+           ANALYZE {schema}.canter_field_stats;"#
+    ))?;
+
+    Ok(())
+}
+
+fn add_term(conn: &Connection, schema: &str, field_id: i64, value: &str) -> Result<i64, Error> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id FROM {schema}.canter_terms WHERE field_id = ? AND value = ?"
+    ))?;
 
     let term_id = stmt
         .query_row(params![field_id, value], |row| row.get::<_, i64>(0))
         .optional()?;
 
     if let Some(term_id) = term_id {
-        let mut stmt =
-            conn.prepare_cached("UPDATE canter_terms SET count = count + 1 WHERE id = ?")?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "UPDATE {schema}.canter_terms SET count = count + 1 WHERE id = ?"
+        ))?;
 
         stmt.execute(params![term_id])?;
 
         Ok(term_id)
     } else {
-        let mut stmt = conn
-            .prepare_cached("INSERT INTO canter_terms (field_id, value, count) VALUES (?, ?, 1)")?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "INSERT INTO {schema}.canter_terms (field_id, value, count) VALUES (?, ?, 1)"
+        ))?;
 
         stmt.execute(params![field_id, value])?;
 
@@ -120,42 +1046,222 @@ fn add_term(conn: &Connection, field_id: i64, value: &str) -> Result<i64, Error>
     }
 }
 
+/// The largest position which can be recorded, chosen independently of the target
+/// so that indexes are portable and positions always fit into `usize` and `INTEGER`.
+pub const MAX_POSITION: usize = u32::MAX as usize;
+
 fn add_posting(
     conn: &Connection,
+    schema: &str,
     term_id: i64,
     document_id: i64,
     position: usize,
+    segment: usize,
+    payload: f64,
 ) -> Result<(), Error> {
+    if position > MAX_POSITION {
+        return Err(Error::TooManyTokens(document_id));
+    }
+
     let mut stmt = conn.prepare_cached(
-        "INSERT INTO canter_postings (term_id, document_id, position) VALUES (?, ?, ?)",
+        &format!("INSERT INTO {schema}.canter_postings (term_id, document_id, position, segment, payload) VALUES (?, ?, ?, ?, ?)"),
     )?;
 
-    stmt.execute(params![term_id, document_id, position])?;
+    stmt.execute(params![term_id, document_id, position, segment, payload])?;
+
+    Ok(())
+}
+
+/// A posting as `(term_id, document_id, position, segment, payload)`.
+type Posting = (i64, i64, usize, usize, f64);
+
+/// Adds a posting to `buffer` if postings are buffered and inserts it immediately otherwise.
+fn buffer_posting(
+    conn: &Connection,
+    schema: &str,
+    buffer: &mut Option<Vec<Posting>>,
+    posting: Posting,
+) -> Result<(), Error> {
+    let (term_id, document_id, position, segment, payload) = posting;
+
+    match buffer {
+        Some(buffer) => {
+            if position > MAX_POSITION {
+                return Err(Error::TooManyTokens(document_id));
+            }
+
+            buffer.push(posting);
+
+            Ok(())
+        }
+        None => add_posting(
+            conn,
+            schema,
+            term_id,
+            document_id,
+            position,
+            segment,
+            payload,
+        ),
+    }
+}
+
+fn has_positions(config: &Config, field_name: &str) -> bool {
+    config
+        .fields
+        .get(field_name)
+        .is_none_or(|config| config.positions)
+}
+
+/// Records a term only once per document with all positions collapsed to zero,
+/// keeping the payload of its first occurrence.
+fn add_presence(
+    conn: &Connection,
+    schema: &str,
+    field_id: i64,
+    document_id: i64,
+    value: &str,
+    payload: f64,
+) -> Result<(), Error> {
+    let mut stmt = conn.prepare_cached(&format!(
+        r#"SELECT COUNT(*) FROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+           WHERE canter_terms.field_id = ? AND canter_terms.value = ? AND canter_postings.document_id = ?"#
+    ))?;
+
+    let present = stmt.query_row(params![field_id, value, document_id], |row| {
+        row.get::<_, usize>(0)
+    })?;
+
+    if present == 0 {
+        let term_id = add_term(conn, schema, field_id, value)?;
+        add_posting(conn, schema, term_id, document_id, 0, 0, payload)?;
+    }
 
     Ok(())
 }
 
 fn add_document(
     conn: &Connection,
+    schema: &str,
     field_id: i64,
     document_id: i64,
+    previous: Option<usize>,
     position: usize,
 ) -> Result<(), Error> {
-    let mut stmt = conn.prepare_cached("INSERT INTO canter_documents (field_id, document_id, count) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET count = ?3")?;
+    if position > MAX_POSITION {
+        return Err(Error::TooManyTokens(document_id));
+    }
+
+    let mut stmt = conn.prepare_cached(&format!("INSERT INTO {schema}.canter_documents (field_id, document_id, count) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET count = ?3"))?;
 
     stmt.execute(params![field_id, document_id, position])?;
 
+    let mut stmt = conn.prepare_cached(&format!("INSERT INTO {schema}.canter_field_stats (field_id, documents, total) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET documents = documents + ?2, total = total + ?3"))?;
+
+    stmt.execute(params![
+        field_id,
+        previous.is_none(),
+        position - previous.unwrap_or(0)
+    ])?;
+
     Ok(())
 }
 
-fn reset_position(conn: &Connection, field_id: i64, document_id: i64) -> Result<usize, Error> {
+/// Removes the contents of a single field of a document, updating term and field statistics accordingly.
+fn remove_field(
+    conn: &Connection,
+    schema: &str,
+    field_id: i64,
+    document_id: i64,
+) -> Result<(), Error> {
+    let Some(previous) = reset_position(conn, schema, field_id, document_id)? else {
+        return Ok(());
+    };
+
+    let terms = {
+        let mut stmt = conn.prepare_cached(&format!(
+            r#"SELECT canter_terms.id, COUNT(*)
+               FROM {schema}.canter_terms JOIN {schema}.canter_postings ON canter_terms.id = canter_postings.term_id
+               WHERE canter_terms.field_id = ? AND canter_postings.document_id = ?
+               GROUP BY canter_terms.id"#
+        ))?;
+
+        stmt.query_map(params![field_id, document_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?
+    };
+
+    for (term_id, count) in terms {
+        let mut stmt = conn.prepare_cached(&format!(
+            "UPDATE {schema}.canter_terms SET count = count - ? WHERE id = ?"
+        ))?;
+        stmt.execute(params![count, term_id])?;
+
+        let mut stmt = conn.prepare_cached(&format!(
+            "DELETE FROM {schema}.canter_terms WHERE id = ? AND count <= 0"
+        ))?;
+        stmt.execute(params![term_id])?;
+
+        let mut stmt = conn.prepare_cached(&format!(
+            "DELETE FROM {schema}.canter_postings WHERE term_id = ? AND document_id = ?"
+        ))?;
+        stmt.execute(params![term_id, document_id])?;
+    }
+
     let mut stmt = conn.prepare_cached(
-        "SELECT count FROM canter_documents WHERE field_id = ? AND document_id = ?",
+        &format!("UPDATE {schema}.canter_field_stats SET documents = documents - 1, total = total - ? WHERE field_id = ?"),
     )?;
+    stmt.execute(params![previous, field_id])?;
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "DELETE FROM {schema}.canter_documents WHERE field_id = ? AND document_id = ?"
+    ))?;
+    stmt.execute(params![field_id, document_id])?;
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "DELETE FROM {schema}.canter_stored WHERE field_id = ? AND document_id = ?"
+    ))?;
+    stmt.execute(params![field_id, document_id])?;
+
+    Ok(())
+}
+
+fn map_document(conn: &Connection, schema: &str, external_id: &str) -> Result<i64, Error> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id FROM {schema}.canter_docmap WHERE external_id = ?"
+    ))?;
+
+    let document_id = stmt
+        .query_row(params![external_id], |row| row.get::<_, i64>(0))
+        .optional()?;
+
+    if let Some(document_id) = document_id {
+        Ok(document_id)
+    } else {
+        let mut stmt = conn.prepare_cached(&format!(
+            "INSERT INTO {schema}.canter_docmap (external_id) VALUES (?)"
+        ))?;
+
+        stmt.execute(params![external_id])?;
+
+        Ok(conn.last_insert_rowid())
+    }
+}
+
+fn reset_position(
+    conn: &Connection,
+    schema: &str,
+    field_id: i64,
+    document_id: i64,
+) -> Result<Option<usize>, Error> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT count FROM {schema}.canter_documents WHERE field_id = ? AND document_id = ?"
+    ))?;
 
     let position = stmt
         .query_row(params![field_id, document_id], |row| row.get::<_, usize>(0))
         .optional()?;
 
-    Ok(position.unwrap_or(0))
+    Ok(position)
 }